@@ -2,6 +2,8 @@ use std::path::PathBuf;
 
 use clap::Parser;
 use clap::Subcommand;
+use clap::ValueEnum;
+use tourmaline::config::{ConfigFormat, ConfigOverride};
 
 const VERSION: &str = concat!(
     env!("CARGO_PKG_VERSION"),
@@ -23,16 +25,144 @@ pub enum Command {
     #[command()]
     InstallFromConfig(InstallFromConfigArgs),
 
+    /// Prints the tasks that would be executed for the given config
+    /// without executing them
+    #[command()]
+    Plan(PlanArgs),
+
+    /// Checks the given config for errors
+    #[command()]
+    Validate(ValidateArgs),
+
+    /// Prints the fstab generated for the given config.
+    /// The generated UUIDs change with every installation
+    #[command()]
+    Fstab(FstabArgs),
+
+    /// Commands to inspect configs
+    #[command(subcommand)]
+    Config(ConfigCommand),
+
+    /// Prints the JSON schema of the config
+    #[command()]
+    Schema,
+
+    /// Lists the disks and partitions that can be used for the installation
+    #[command()]
+    Devices(DevicesArgs),
+
+    /// Lists the locales, keymaps or timezones that can be used in the config
+    #[command()]
+    List(ListArgs),
+
+    /// Serves the task executor over JSON-RPC on a unix socket
+    #[command()]
+    Daemon(DaemonArgs),
+
     /// Generates empty script files for the installation
     #[command()]
     GenerateScripts(GenerateScriptsArgs),
 }
 
+#[derive(Debug, Clone, Subcommand)]
+pub enum ConfigCommand {
+    /// Prints the config with all base configs and overrides applied
+    #[command()]
+    Render(RenderArgs),
+}
+
+/// The arguments shared by all commands reading a config
 #[derive(Debug, Clone, Parser)]
-pub struct InstallFromConfigArgs {
-    /// The path to the json config file
+pub struct ConfigArgs {
+    /// The path to the json, toml or yaml config file
     #[arg()]
     pub path: PathBuf,
+
+    /// The format of the config file. Taken from the file extension by default
+    #[arg(long)]
+    pub format: Option<ConfigFormat>,
+
+    /// Overrides a config value, e.g. `--set network.hostname=foo`.
+    /// Applied after merging the base configs
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    pub overrides: Vec<ConfigOverride>,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct InstallFromConfigArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+
+    /// Skips the tasks that have already been completed by a previous installation
+    #[arg(long)]
+    pub resume: bool,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct PlanArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct ValidateArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct FstabArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct RenderArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+
+    /// The format of the rendered config
+    #[arg(long, default_value = "json")]
+    pub output: ConfigFormat,
+
+    /// Prints passwords and keys instead of redacting them
+    #[arg(long)]
+    pub show_secrets: bool,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct DevicesArgs {
+    /// Prints the devices as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct ListArgs {
+    #[arg(value_enum)]
+    pub kind: ListKind,
+
+    /// Prints the values as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ListKind {
+    Locales,
+    Keymaps,
+    Timezones,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct DaemonArgs {
+    /// The path of the unix socket to listen on
+    #[arg(long, default_value = "/run/tourmaline.sock")]
+    pub socket: PathBuf,
+
+    /// The group allowed to connect to the socket besides root
+    #[arg(long)]
+    pub group: Option<String>,
 }
 
 #[derive(Debug, Clone, Parser)]
@@ -0,0 +1,108 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use embed_nu::rusty_value::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::validation::{Validate, Validator};
+
+/// The supported compression algorithms with their minimum and maximum level
+const COMPRESSION_ALGORITHMS: &[(&str, Option<(u8, u8)>)] = &[
+    ("zlib", Some((1, 9))),
+    ("lzo", None),
+    ("zstd", Some((1, 15))),
+];
+
+/// The subvolumes and mount options of a btrfs filesystem
+#[derive(Clone, Debug, Default, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct BtrfsConfig {
+    /// The subvolumes created on the filesystem, e.g. `@` mounted at `/`
+    #[serde(default)]
+    pub subvolumes: Vec<Subvolume>,
+    /// The compression used for all mounts, e.g. `zstd` or `zstd:3`
+    pub compression: Option<String>,
+    /// Mount options used for all mounts of the filesystem, e.g. `noatime`
+    #[serde(default)]
+    pub mount_options: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct Subvolume {
+    /// The path of the subvolume relative to the top level, e.g. `@home` or `@var/log`
+    pub name: String,
+    pub mountpoint: Option<PathBuf>,
+    /// Additional mount options for this subvolume only
+    #[serde(default)]
+    pub mount_options: Vec<String>,
+}
+
+impl BtrfsConfig {
+    /// Returns the options of a mount of the filesystem with the given specific options
+    pub fn mount_options(&self, options: &[String]) -> Vec<String> {
+        self.compression
+            .iter()
+            .map(|c| format!("compress={c}"))
+            .chain(self.mount_options.iter().cloned())
+            .chain(options.iter().cloned())
+            .collect()
+    }
+}
+
+impl Validate for BtrfsConfig {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        if let Some(compression) = &self.compression {
+            let (algorithm, level) = match compression.split_once(':') {
+                Some((algorithm, level)) => (algorithm, Some(level)),
+                None => (compression.as_str(), None),
+            };
+            match COMPRESSION_ALGORITHMS.iter().find(|(a, _)| *a == algorithm) {
+                Some((_, levels)) => match (level, levels) {
+                    (Some(_), None) => validator.error(
+                        format!("{path}.compression"),
+                        format!("{algorithm} doesn't support compression levels"),
+                    ),
+                    (Some(level), Some((min, max)))
+                        if !level
+                            .parse()
+                            .map(|l| (*min..=*max).contains(&l))
+                            .unwrap_or(false) =>
+                    {
+                        validator.error(
+                            format!("{path}.compression"),
+                            format!("the level of {algorithm} must be between {min} and {max}"),
+                        )
+                    }
+                    _ => {}
+                },
+                None => validator.error(
+                    format!("{path}.compression"),
+                    format!(
+                        "unknown compression algorithm {algorithm}. Expected zlib, lzo or zstd"
+                    ),
+                ),
+            }
+        }
+        let mut names = HashSet::new();
+
+        for (i, subvolume) in self.subvolumes.iter().enumerate() {
+            let name = &subvolume.name;
+
+            if name.is_empty()
+                || name.starts_with('/')
+                || name
+                    .split('/')
+                    .any(|c| c.is_empty() || c == "." || c == "..")
+            {
+                validator.error(
+                    format!("{path}.subvolumes[{i}].name"),
+                    "the subvolume name must be a relative path without empty, . or .. components",
+                );
+            } else if !names.insert(name) {
+                validator.error(
+                    format!("{path}.subvolumes[{i}].name"),
+                    format!("the subvolume {name} is defined more than once"),
+                );
+            }
+        }
+    }
+}
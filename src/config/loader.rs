@@ -0,0 +1,632 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use crate::{
+    error::{AppError, AppResult},
+    validation::Validator,
+};
+
+/// The key listing the base configs a config extends
+const EXTENDS_KEY: &str = "extends";
+
+/// Keys ending with this suffix append their list to the list of the base config
+/// instead of replacing it, e.g. `"packages+": ["vim"]`
+const APPEND_SUFFIX: char = '+';
+
+/// Options of older config versions that are rejected with a hint to their replacement
+/// unless they are disabled
+const REPLACED_KEYS: &[(&str, &str)] = &[(
+    "enable_flatpak",
+    "replaced by `flatpak`. An empty `flatpak` record installs flatpak with Flathub",
+)];
+
+/// The file format of a config
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Guesses the format from the extension of the given path
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+
+    fn parse_value(&self, contents: &str) -> AppResult<Value> {
+        let value = match self {
+            ConfigFormat::Json => serde_json::from_str(contents)?,
+            ConfigFormat::Toml => toml::from_str(contents)?,
+            ConfigFormat::Yaml => serde_yaml::from_str(contents)?,
+        };
+
+        Ok(value)
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "toml" => Ok(Self::Toml),
+            "yaml" | "yml" => Ok(Self::Yaml),
+            other => Err(format!("Unknown config format {other}")),
+        }
+    }
+}
+
+impl fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigFormat::Json => "json".fmt(f),
+            ConfigFormat::Toml => "toml".fmt(f),
+            ConfigFormat::Yaml => "yaml".fmt(f),
+        }
+    }
+}
+
+/// Overrides a single config value given as `key.path=value`.
+/// The value is parsed as JSON and used as a plain string if that fails
+#[derive(Clone, Debug)]
+pub struct ConfigOverride {
+    pub path: Vec<String>,
+    pub value: Value,
+}
+
+impl FromStr for ConfigOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected key.path=value but got {s}"))?;
+        let path = path
+            .split('.')
+            .map(|key| key.trim().to_owned())
+            .collect::<Vec<_>>();
+
+        if path.iter().any(String::is_empty) {
+            return Err(format!("Invalid key path in {s}"));
+        }
+        let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_owned()));
+
+        Ok(Self { path, value })
+    }
+}
+
+impl ConfigOverride {
+    /// Sets the value in the given config creating missing records on the way
+    pub fn apply(&self, config: &mut Value) -> AppResult<()> {
+        let mut target = config;
+
+        for key in &self.path {
+            if target.is_null() {
+                *target = Value::Object(Map::new());
+            }
+            target = match target {
+                Value::Object(map) => map.entry(key.to_owned()).or_insert(Value::Null),
+                Value::Array(list) => key
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|i| list.get_mut(i))
+                    .ok_or_else(|| self.invalid(format!("{key} is not a valid list index")))?,
+                _ => return Err(self.invalid(format!("{key} is not inside a record or list"))),
+            };
+        }
+        *target = self.value.clone();
+
+        Ok(())
+    }
+
+    fn invalid<S: ToString>(&self, message: S) -> AppError {
+        AppError::InvalidOverride(self.path.join("."), message.to_string())
+    }
+}
+
+/// A config file with the values it sets itself
+pub(super) struct Layer {
+    path: PathBuf,
+    value: Value,
+}
+
+/// A segment of the JSON path of a config value
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// A config value that couldn't be deserialized
+pub(super) struct InvalidValue {
+    path: Vec<PathSegment>,
+    message: String,
+}
+
+impl InvalidValue {
+    /// Returns the file or override that sets the value last
+    pub fn defining_layer(&self, layers: &[Layer], overrides: &[ConfigOverride]) -> Option<String> {
+        let path = self
+            .path
+            .iter()
+            .map(|segment| match segment {
+                PathSegment::Key(key) => key.to_owned(),
+                PathSegment::Index(index) => index.to_string(),
+            })
+            .collect::<Vec<_>>();
+        // overrides set whole values so they define everything inside and around them
+        let config_override = overrides.iter().rev().find(|o| {
+            let len = o.path.len().min(path.len());
+            o.path[..len] == path[..len]
+        });
+
+        match config_override {
+            Some(config_override) => Some(format!("--set {}", config_override.path.join("."))),
+            None => layers
+                .iter()
+                .rev()
+                .find(|l| defines(&l.value, &self.path))
+                .map(|l| l.path.display().to_string()),
+        }
+    }
+
+    pub fn into_error(self, layer: Option<String>) -> AppError {
+        let path = self
+            .path
+            .iter()
+            .map(|segment| match segment {
+                PathSegment::Key(key) => format!(".{key}"),
+                PathSegment::Index(index) => format!("[{index}]"),
+            })
+            .collect::<String>();
+
+        AppError::InvalidConfigValue {
+            path: format!("${path}"),
+            layer,
+            message: self.message,
+        }
+    }
+}
+
+/// Deserializes the merged config. On errors the path of the offending value is
+/// recovered by parsing the config again as JSON text which has positions
+pub(super) fn deserialize<T: DeserializeOwned>(value: Value) -> Result<T, InvalidValue> {
+    let error = match serde_json::from_value(value.clone()) {
+        Ok(config) => return Ok(config),
+        Err(e) => e,
+    };
+    let text = serde_json::to_string_pretty(&value).unwrap_or_default();
+    let (path, error) = match serde_json::from_str::<T>(&text) {
+        Err(e) => (path_at(&text, e.line(), e.column()), e),
+        Ok(_) => (Vec::new(), error),
+    };
+    let message = error.to_string();
+    // the position refers to the generated text and not to any of the files
+    let message = match message.rsplit_once(" at line ") {
+        Some((message, _)) => message.to_owned(),
+        None => message,
+    };
+
+    Err(InvalidValue { path, message })
+}
+
+/// Returns the path of the value at the given one-based position in the JSON text
+fn path_at(text: &str, line: usize, column: usize) -> Vec<PathSegment> {
+    enum Frame {
+        Object(Option<String>),
+        Array(Option<usize>),
+    }
+    let line_start = text
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum::<usize>();
+    let end = (0..=(line_start + column).min(text.len()))
+        .rev()
+        .find(|i| text.is_char_boundary(*i))
+        .unwrap_or_default();
+    let mut chars = text[..end].chars();
+    let mut frames = Vec::new();
+    let mut expects_key = false;
+
+    // a value starting in a list is the next element of it
+    let start_value = |frames: &mut Vec<Frame>| {
+        if let Some(Frame::Array(index @ None)) = frames.last_mut() {
+            *index = Some(0);
+        }
+    };
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let mut string = String::new();
+
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => string.extend(chars.next()),
+                        '"' => break,
+                        c => string.push(c),
+                    }
+                }
+                match frames.last_mut() {
+                    Some(Frame::Object(key)) if expects_key => {
+                        *key = Some(string);
+                        expects_key = false;
+                    }
+                    _ => start_value(&mut frames),
+                }
+            }
+            '{' => {
+                start_value(&mut frames);
+                frames.push(Frame::Object(None));
+                expects_key = true;
+            }
+            '[' => {
+                start_value(&mut frames);
+                frames.push(Frame::Array(None));
+            }
+            '}' | ']' => {
+                frames.pop();
+            }
+            ',' => match frames.last_mut() {
+                Some(Frame::Object(_)) => expects_key = true,
+                Some(Frame::Array(index)) => *index = index.map(|i| i + 1),
+                None => {}
+            },
+            c if !c.is_whitespace() && c != ':' => start_value(&mut frames),
+            _ => {}
+        }
+    }
+
+    frames
+        .into_iter()
+        .filter_map(|frame| match frame {
+            Frame::Object(key) => key.map(PathSegment::Key),
+            Frame::Array(index) => index.map(PathSegment::Index),
+        })
+        .collect()
+}
+
+/// Returns if the value of the layer contains the given path
+fn defines(mut value: &Value, path: &[PathSegment]) -> bool {
+    for segment in path {
+        value = match (segment, value) {
+            (PathSegment::Key(key), Value::Object(map)) => {
+                match map
+                    .get(key)
+                    .or_else(|| map.get(&format!("{key}{APPEND_SUFFIX}")))
+                {
+                    Some(value) => value,
+                    None => return false,
+                }
+            }
+            (PathSegment::Index(index), Value::Array(list)) => match list.get(*index) {
+                Some(value) => value,
+                // the elements of appended lists are spread over the layers
+                None => return true,
+            },
+            _ => return false,
+        };
+    }
+
+    true
+}
+
+/// Loads the config file as a value merged on top of all the configs it extends.
+/// Also returns the values of the single files in the order they are merged in
+pub(super) fn load_layered(
+    path: &Path,
+    format: Option<ConfigFormat>,
+) -> AppResult<(Value, Vec<Layer>)> {
+    let mut layers = Vec::new();
+    let value = load_file(path, format, &mut Vec::new(), &mut layers)?;
+
+    Ok((value, layers))
+}
+
+fn load_file(
+    path: &Path,
+    format: Option<ConfigFormat>,
+    stack: &mut Vec<PathBuf>,
+    layers: &mut Vec<Layer>,
+) -> AppResult<Value> {
+    let canonical = fs::canonicalize(path)?;
+
+    if let Some(index) = stack.iter().position(|p| p == &canonical) {
+        let mut cycle = stack[index..].to_vec();
+        cycle.push(canonical);
+        return Err(AppError::ExtendsCycle(cycle));
+    }
+    let format = format
+        .or_else(|| ConfigFormat::from_path(path))
+        .ok_or_else(|| AppError::UnknownConfigFormat(path.to_owned()))?;
+    let contents = fs::read_to_string(path)?;
+    let mut value = format.parse_value(&contents)?;
+    let bases = take_extends(path, &mut value)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    stack.push(canonical);
+    let mut merged = Value::Object(Map::new());
+
+    for base in bases {
+        let base_path = dir.join(base);
+        // base configs without a known extension use the format of the extending config
+        let base_format = ConfigFormat::from_path(&base_path).unwrap_or(format);
+        merge(
+            &mut merged,
+            load_file(&base_path, Some(base_format), stack, layers)?,
+        );
+    }
+    stack.pop();
+    layers.push(Layer {
+        path: path.to_owned(),
+        value: value.clone(),
+    });
+    merge(&mut merged, value);
+
+    Ok(merged)
+}
+
+/// Reports the enabled options of older config versions. They would be ignored otherwise
+pub(super) fn check_replaced_keys(value: &Value) -> AppResult<()> {
+    let mut validator = Validator::default();
+
+    for (key, hint) in REPLACED_KEYS {
+        if value.get(key).is_some_and(|v| v != &Value::Bool(false)) {
+            validator.error(format!("$.{key}"), hint);
+        }
+    }
+    let errors = validator.into_errors();
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::InvalidConfig(errors))
+    }
+}
+
+/// Removes the `extends` key from the config and returns the listed paths
+fn take_extends(path: &Path, value: &mut Value) -> AppResult<Vec<PathBuf>> {
+    let invalid = || AppError::InvalidExtends(path.to_owned());
+    let extends = match value.as_object_mut().and_then(|o| o.remove(EXTENDS_KEY)) {
+        Some(extends) => extends,
+        None => return Ok(Vec::new()),
+    };
+
+    match extends {
+        Value::String(base) => Ok(vec![PathBuf::from(base)]),
+        Value::Array(bases) => bases
+            .into_iter()
+            .map(|base| match base {
+                Value::String(base) => Ok(PathBuf::from(base)),
+                _ => Err(invalid()),
+            })
+            .collect(),
+        _ => Err(invalid()),
+    }
+}
+
+/// Deep merges the overlay into the base. Records are merged key by key
+/// while all other values including lists are replaced unless the key has the append suffix
+fn merge(base: &mut Value, overlay: Value) {
+    match overlay {
+        Value::Object(overlay) => {
+            if !base.is_object() {
+                *base = Value::Object(Map::new());
+            }
+            if let Value::Object(base) = base {
+                for (key, value) in overlay {
+                    match key.strip_suffix(APPEND_SUFFIX) {
+                        Some(key) => append(base.entry(key).or_insert(Value::Null), value),
+                        None => merge(base.entry(key).or_insert(Value::Null), value),
+                    }
+                }
+            }
+        }
+        overlay => *base = overlay,
+    }
+}
+
+fn append(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Array(base), Value::Array(overlay)) => base.extend(overlay),
+        (base, overlay) => merge(base, overlay),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+
+    #[allow(dead_code)]
+    #[derive(Debug, Deserialize)]
+    struct Outer {
+        name: String,
+        inner: Inner,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Debug, Deserialize)]
+    struct Inner {
+        items: Vec<Item>,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Debug, Deserialize)]
+    struct Item {
+        size: u32,
+    }
+
+    fn error_path(value: Value) -> String {
+        let error = deserialize::<Outer>(value).unwrap_err().into_error(None);
+
+        match error {
+            AppError::InvalidConfigValue { path, .. } => path,
+            e => panic!("unexpected error {e}"),
+        }
+    }
+
+    fn write(dir: &Path, name: &str, value: Value) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, value.to_string()).unwrap();
+
+        path
+    }
+
+    fn set(s: &str) -> ConfigOverride {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn finds_the_path_in_nested_records_and_lists() {
+        let value = json!({
+            "name": "test",
+            "inner": { "items": [{ "size": 1 }, { "size": 2 }, { "size": "big" }] },
+        });
+
+        assert_eq!(error_path(value), "$.inner.items[2].size");
+    }
+
+    #[test]
+    fn finds_the_path_after_nested_lists() {
+        let text = r#"{"a": [[1, 2], [3]], "b": {"c": [{}, {"d": true}]}}"#;
+        let column = text.find("true").unwrap() + 1;
+
+        assert_eq!(
+            path_at(text, 1, column),
+            [
+                PathSegment::Key("b".into()),
+                PathSegment::Key("c".into()),
+                PathSegment::Index(1),
+                PathSegment::Key("d".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_escaped_quotes_in_strings() {
+        let value = json!({
+            "name": "a \"quoted\" name with [brackets], {braces} and \\",
+            "inner": { "items": [{ "size": "x" }] },
+        });
+
+        assert_eq!(error_path(value), "$.inner.items[0].size");
+
+        let text = r#"{"say \"a\": [": 1, "b": [0, "x"]}"#;
+        let column = text.find("\"x\"").unwrap() + 1;
+        assert_eq!(
+            path_at(text, 1, column),
+            [PathSegment::Key("b".into()), PathSegment::Index(1)]
+        );
+    }
+
+    #[test]
+    fn merges_records_and_replaces_lists() {
+        let mut base = json!({ "a": { "b": 1, "c": [1, 2] }, "d": true });
+        merge(&mut base, json!({ "a": { "c": [3], "e": "x" } }));
+
+        assert_eq!(
+            base,
+            json!({ "a": { "b": 1, "c": [3], "e": "x" }, "d": true })
+        );
+    }
+
+    #[test]
+    fn appends_lists() {
+        let mut base = json!({ "packages": ["vim"] });
+        merge(&mut base, json!({ "packages+": ["git"] }));
+        assert_eq!(base, json!({ "packages": ["vim", "git"] }));
+
+        let mut base = json!({});
+        merge(&mut base, json!({ "extra": { "packages+": ["git"] } }));
+        assert_eq!(base, json!({ "extra": { "packages": ["git"] } }));
+    }
+
+    #[test]
+    fn applies_overrides() {
+        let mut config = json!({ "users": [{ "name": "a" }], "network": null });
+
+        set("users.0.name=b").apply(&mut config).unwrap();
+        set("network.hostname=host").apply(&mut config).unwrap();
+        set("enable_zramd=true").apply(&mut config).unwrap();
+        assert_eq!(
+            config,
+            json!({
+                "users": [{ "name": "b" }],
+                "network": { "hostname": "host" },
+                "enable_zramd": true,
+            })
+        );
+
+        assert!(set("users.1.name=c").apply(&mut config).is_err());
+        assert!(set("enable_zramd.value=1").apply(&mut config).is_err());
+        assert!("users..name=c".parse::<ConfigOverride>().is_err());
+    }
+
+    #[test]
+    fn overrides_keys_replaced_by_extending_configs() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "base.json",
+            json!({ "name": "base", "inner": { "items": [{ "size": 1 }] } }),
+        );
+        let child = write(
+            dir.path(),
+            "child.json",
+            json!({ "extends": "base.json", "inner": { "items": [{ "size": 2 }] } }),
+        );
+
+        let (mut value, layers) = load_layered(&child, None).unwrap();
+        assert_eq!(layers.len(), 2);
+        assert_eq!(value["inner"]["items"], json!([{ "size": 2 }]));
+        assert!(value.get(EXTENDS_KEY).is_none());
+
+        set("inner.items.0.size=3").apply(&mut value).unwrap();
+        assert_eq!(value["inner"]["items"], json!([{ "size": 3 }]));
+    }
+
+    #[test]
+    fn reports_the_layer_defining_an_invalid_value() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "base.json",
+            json!({ "name": 1, "inner": { "items": [{ "size": 1 }] } }),
+        );
+        let child = write(
+            dir.path(),
+            "child.json",
+            json!({ "extends": "base.json", "inner": { "items+": [{ "size": "x" }] } }),
+        );
+        let (value, layers) = load_layered(&child, None).unwrap();
+        let layer = |value: Value, overrides: &[ConfigOverride]| {
+            deserialize::<Outer>(value)
+                .unwrap_err()
+                .defining_layer(&layers, overrides)
+        };
+
+        // the list is appended to in the child so its new element is defined there
+        assert_eq!(layer(value.clone(), &[]), Some(child.display().to_string()));
+
+        let mut fixed = value;
+        let overrides = [set("inner.items=[{\"size\": 1}]")];
+        overrides[0].apply(&mut fixed).unwrap();
+        let base = dir.path().join("base.json").display().to_string();
+        assert_eq!(layer(fixed.clone(), &overrides), Some(base));
+
+        let overrides = [overrides[0].clone(), set("name=true")];
+        overrides[1].apply(&mut fixed).unwrap();
+        assert_eq!(layer(fixed, &overrides), Some("--set name".to_owned()));
+    }
+}
@@ -0,0 +1,247 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use embed_nu::rusty_value::*;
+use schemars::{schema::RootSchema, schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+mod loader;
+pub use loader::{ConfigFormat, ConfigOverride};
+
+use crate::{
+    error::AppResult,
+    fstab::FstabIdentifier,
+    secrets::Password,
+    tasks::{
+        validate_custom_args, validate_kernel_cmdline, BootloaderConfig, BootloaderPreset,
+        CustomArgs, DesktopConfig, ExtraPackages, FlatpakConfig, InitramfsConfig, Kernel,
+        KernelConfig, LocaleConfig, NetworkBackend, NetworkConfig, PartitionTable, Partitions,
+        PartitionsConfig, RootUserConfig, TaskManifest, UnakiteConfig, UsersConfig,
+    },
+    utils,
+    validation::{Validate, ValidationError, Validator},
+};
+
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct Config {
+    pub locale: LocaleConfig,
+    pub network: NetworkConfig,
+    pub partitions: PartitionsConfig,
+    pub bootloader: BootloaderConfig,
+    pub kernels: KernelConfig,
+    /// The initramfs generator and the hooks and modules it includes
+    #[serde(default)]
+    pub initramfs: InitramfsConfig,
+    /// Additional kernel parameters. The parameters needed to find
+    /// the root filesystem and swap are added automatically
+    #[serde(default)]
+    pub kernel_cmdline: Vec<String>,
+    pub desktop: DesktopConfig,
+    pub users: UsersConfig,
+    pub root_user: RootUserConfig,
+    pub unakite: Option<UnakiteConfig>,
+    pub extra_packages: ExtraPackages,
+    pub enable_timeshift: bool,
+    /// Installs flatpak with the given remotes and applications
+    pub flatpak: Option<FlatpakConfig>,
+    pub enable_zramd: bool,
+    /// Arguments of the user defined tasks by task name
+    #[serde(default)]
+    pub custom_tasks: HashMap<String, CustomArgs>,
+}
+
+impl Config {
+    /// Loads the config from the given file and resolves the base configs it extends.
+    /// The overrides are applied last. If no format is given it is taken from the file extension
+    pub fn load(
+        path: &Path,
+        format: Option<ConfigFormat>,
+        overrides: &[ConfigOverride],
+    ) -> AppResult<Self> {
+        let (mut value, layers) = loader::load_layered(path, format)?;
+        for config_override in overrides {
+            config_override.apply(&mut value)?;
+        }
+        loader::check_replaced_keys(&value)?;
+
+        loader::deserialize(value).map_err(|e| {
+            let layer = e.defining_layer(&layers, overrides);
+            e.into_error(layer)
+        })
+    }
+
+    /// Deserializes the config from a JSON value
+    pub fn from_value(value: serde_json::Value) -> AppResult<Self> {
+        loader::check_replaced_keys(&value)?;
+
+        loader::deserialize(value).map_err(|e| e.into_error(None))
+    }
+
+    /// Serializes the config in the given format
+    pub fn render(&self, format: ConfigFormat) -> AppResult<String> {
+        let rendered = match format {
+            ConfigFormat::Json => serde_json::to_string_pretty(self)?,
+            // converting to a toml value first moves plain values in front of tables
+            ConfigFormat::Toml => toml::Value::try_from(self)?.to_string(),
+            ConfigFormat::Yaml => serde_yaml::to_string(self)?,
+        };
+
+        Ok(rendered)
+    }
+
+    /// Returns the JSON schema of the config and all nested task configs
+    pub fn json_schema() -> RootSchema {
+        schema_for!(Config)
+    }
+
+    /// Checks the config for semantic errors and returns all problems found
+    pub fn validate(&self) -> Vec<ValidationError> {
+        let mut validator = Validator::default();
+        Validate::validate(self, "$", &mut validator);
+
+        validator.into_errors()
+    }
+
+    /// Returns a copy of the config with all passwords removed
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        config.root_user.password = Password::Redacted;
+        config
+            .users
+            .users
+            .iter_mut()
+            .for_each(|u| u.password = Password::Redacted);
+        config.partitions.redact();
+        config.network.redact();
+
+        config
+    }
+
+    /// Returns a copy of the config with the plain passwords hashed.
+    /// The salts are derived from the given seed so the hashes stay the same
+    /// for every script and hook of an installation and when it is resumed
+    pub fn with_hashed_passwords(mut self, seed: &str) -> Self {
+        self.root_user.password = self.root_user.password.hashed(seed, "password:root");
+        for user in &mut self.users.users {
+            let purpose = format!("password:{}", user.name);
+            user.password = user.password.clone().hashed(seed, &purpose);
+        }
+
+        self
+    }
+
+    pub(crate) fn empty() -> Self {
+        Self {
+            locale: LocaleConfig {
+                locale: Vec::new(),
+                keymap: String::new(),
+                timezone: String::new(),
+            },
+            network: NetworkConfig {
+                hostname: String::new(),
+                ipv6_loopback: false,
+                backend: NetworkBackend::NetworkManager,
+                interfaces: Vec::new(),
+            },
+            partitions: PartitionsConfig {
+                device: PathBuf::new(),
+                efi_partition: false,
+                table: PartitionTable::Gpt,
+                partitions: Partitions::Auto,
+                disks: Vec::new(),
+                raid_arrays: Vec::new(),
+                volume_groups: Vec::new(),
+                fstab_identifier: FstabIdentifier::Uuid,
+            },
+            bootloader: BootloaderConfig {
+                preset: BootloaderPreset::GrubEfi,
+                location: PathBuf::new(),
+                timeout: None,
+                default_entry: None,
+                grub: None,
+                systemd_boot: None,
+                limine: None,
+                refind: None,
+            },
+            kernels: KernelConfig {
+                default: Kernel::new(""),
+                additional: Vec::new(),
+            },
+            initramfs: InitramfsConfig::default(),
+            kernel_cmdline: Vec::new(),
+            desktop: DesktopConfig::KdePlasma,
+            users: UsersConfig { users: Vec::new() },
+            root_user: RootUserConfig {
+                password: Password::Redacted,
+            },
+            unakite: None,
+            extra_packages: ExtraPackages::default(),
+            enable_timeshift: false,
+            flatpak: None,
+            enable_zramd: false,
+            custom_tasks: HashMap::new(),
+        }
+    }
+}
+
+impl Validate for Config {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        validator
+            .validate(&format!("{path}.locale"), &self.locale)
+            .validate(&format!("{path}.network"), &self.network)
+            .validate(&format!("{path}.partitions"), &self.partitions)
+            .validate(&format!("{path}.bootloader"), &self.bootloader)
+            .validate(&format!("{path}.kernels"), &self.kernels)
+            .validate(&format!("{path}.initramfs"), &self.initramfs)
+            .validate(&format!("{path}.users"), &self.users)
+            .validate(&format!("{path}.extra_packages"), &self.extra_packages)
+            .validate(&format!("{path}.root_user"), &self.root_user);
+
+        if let Some(unakite) = &self.unakite {
+            validator.validate(&format!("{path}.unakite"), unakite);
+        }
+        let users = self
+            .users
+            .users
+            .iter()
+            .map(|u| u.name.clone())
+            .collect::<Vec<_>>();
+
+        if let Some(flatpak) = &self.flatpak {
+            validator.validate(&format!("{path}.flatpak"), flatpak);
+            flatpak.validate_users(&format!("{path}.flatpak"), &users, validator);
+        }
+        // the parameters for the root filesystem are only derived if the partitions are known
+        let derived_cmdline = !matches!(self.partitions.partitions, Partitions::Auto);
+        validate_kernel_cmdline(
+            &format!("{path}.kernel_cmdline"),
+            &self.kernel_cmdline,
+            derived_cmdline,
+            validator,
+        );
+        self.extra_packages
+            .validate_users(&format!("{path}.extra_packages"), &users, validator);
+        self.kernels.validate_setup(
+            &format!("{path}.kernels"),
+            &self.initramfs,
+            derived_cmdline,
+            validator,
+        );
+        self.bootloader.validate_setup(
+            &format!("{path}.bootloader"),
+            &self.partitions,
+            &self.kernels,
+            validator,
+        );
+        // broken manifests are reported when the installation loads them
+        let manifests = TaskManifest::load_all(&utils::TASK_PATH).unwrap_or_default();
+        validate_custom_args(
+            &format!("{path}.custom_tasks"),
+            &self.custom_tasks,
+            &manifests,
+            validator,
+        );
+    }
+}
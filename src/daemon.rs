@@ -0,0 +1,445 @@
+use std::{
+    cell::RefCell,
+    fs::Permissions,
+    os::unix::fs::{chown, FileTypeExt, PermissionsExt},
+    path::Path,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::{
+    fs,
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    runtime,
+    sync::{broadcast, mpsc, oneshot},
+    task::{self, JoinHandle, LocalSet},
+};
+
+use crate::{
+    config::Config,
+    devices::DeviceScanner,
+    error::{AppError, AppResult},
+    events::TaskEvent,
+    validation::ValidationError,
+    TaskExecutor, EVENT_CAPACITY,
+};
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+/// The socket is only accessible to root and the members of the configured group
+const SOCKET_MODE: u32 = 0o660;
+
+const GROUP_FILE: &str = "/etc/group";
+
+/// Serves the task executor over newline delimited JSON-RPC 2.0 on the given unix socket.
+///
+/// Supported methods:
+/// - `submit_config {config}` stores the config used for the next installation
+/// - `validate_config {config}` checks a config without storing it
+/// - `start_install {resume?}` starts the installation with the submitted config
+/// - `cancel_install` cancels the running installation after the current task
+/// - `status` returns the state of the installation
+/// - `list_devices` returns the disks and partitions that can be used for the installation
+/// - `subscribe` sends all task events as `event` notifications on this connection
+///
+/// The socket is owned by the given group if one is set
+pub async fn serve(socket: &Path, group: Option<&str>) -> AppResult<()> {
+    remove_stale_socket(socket).await?;
+    let listener = UnixListener::bind(socket)?;
+    fs::set_permissions(socket, Permissions::from_mode(SOCKET_MODE)).await?;
+
+    if let Some(group) = group {
+        chown(socket, None, Some(group_id(group).await?))?;
+    }
+    let daemon = Rc::new(Daemon::new());
+    tracing::info!("Listening on {}", socket.display());
+
+    LocalSet::new()
+        .run_until(async move {
+            loop {
+                let (stream, _) = listener.accept().await?;
+                let daemon = Rc::clone(&daemon);
+
+                task::spawn_local(async move {
+                    if let Err(e) = daemon.handle_connection(stream).await {
+                        tracing::error!("Connection failed: {e}");
+                    }
+                });
+            }
+        })
+        .await
+}
+
+/// The state of the installation managed by the daemon
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum InstallStatus {
+    Idle,
+    Running {
+        task: Option<String>,
+        percentage: f32,
+    },
+    Completed,
+    Failed {
+        error: String,
+    },
+    Cancelled,
+}
+
+#[derive(Deserialize)]
+struct Request {
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Serialize)]
+struct Response {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+#[derive(Serialize)]
+struct Notification<'a> {
+    jsonrpc: &'static str,
+    method: &'static str,
+    params: &'a TaskEvent,
+}
+
+#[derive(Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new<S: ToString>(code: i64, message: S) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<AppError> for RpcError {
+    fn from(e: AppError) -> Self {
+        Self::new(SERVER_ERROR, e)
+    }
+}
+
+impl From<serde_json::Error> for RpcError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::new(INVALID_PARAMS, e)
+    }
+}
+
+struct Daemon {
+    config: RefCell<Option<Config>>,
+    status: RefCell<InstallStatus>,
+    /// Set to cancel the running installation
+    cancel: RefCell<Option<Arc<AtomicBool>>>,
+    events: broadcast::Sender<TaskEvent>,
+}
+
+impl Daemon {
+    fn new() -> Self {
+        Self {
+            config: RefCell::new(None),
+            status: RefCell::new(InstallStatus::Idle),
+            cancel: RefCell::new(None),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+
+    async fn handle_connection(self: Rc<Self>, stream: UnixStream) -> AppResult<()> {
+        let (reader, mut writer) = stream.into_split();
+        let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+        let mut subscriptions = Vec::new();
+
+        let writer_task = task::spawn_local(async move {
+            while let Some(message) = rx.recv().await {
+                if writer.write_all(message.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+        let mut lines = BufReader::new(reader).lines();
+
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match serde_json::from_str::<Request>(&line) {
+                Ok(request) => {
+                    let id = request.id.clone();
+                    let result = if request.method == "subscribe" {
+                        subscriptions.push(self.subscribe(tx.clone()));
+                        Ok(Value::Null)
+                    } else {
+                        self.handle_request(request)
+                    };
+                    // requests without an id are notifications and don't get a response
+                    id.map(|id| Response::new(id, result))
+                }
+                Err(e) => Some(Response::new(
+                    Value::Null,
+                    Err(RpcError::new(PARSE_ERROR, e)),
+                )),
+            };
+
+            if let Some(response) = response {
+                let _ = tx.send(serde_json::to_string(&response)? + "\n");
+            }
+        }
+        subscriptions.iter().for_each(JoinHandle::abort);
+        drop(tx);
+        let _ = writer_task.await;
+
+        Ok(())
+    }
+
+    fn handle_request(self: &Rc<Self>, request: Request) -> Result<Value, RpcError> {
+        match request.method.as_str() {
+            "submit_config" => {
+                let config = parse_config(request.params)?;
+                *self.config.borrow_mut() = Some(config);
+                Ok(Value::Null)
+            }
+            "validate_config" => {
+                let errors = match parse_config(request.params) {
+                    Ok(config) => validate(config),
+                    Err(e) => vec![ValidationError {
+                        path: "$".into(),
+                        message: e.message,
+                    }],
+                };
+                Ok(json!({ "valid": errors.is_empty(), "errors": errors }))
+            }
+            "start_install" => {
+                let resume = request
+                    .params
+                    .get("resume")
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false);
+                self.start_install(resume)?;
+                Ok(Value::Null)
+            }
+            "cancel_install" => {
+                self.cancel_install()?;
+                Ok(Value::Null)
+            }
+            "status" => Ok(json!({
+                "config_submitted": self.config.borrow().is_some(),
+                "install": &*self.status.borrow(),
+            })),
+            "list_devices" => Ok(serde_json::to_value(DeviceScanner::default().scan()?)?),
+            method => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method {method}"),
+            )),
+        }
+    }
+
+    fn start_install(self: &Rc<Self>, resume: bool) -> Result<(), RpcError> {
+        if matches!(*self.status.borrow(), InstallStatus::Running { .. }) {
+            return Err(RpcError::new(
+                SERVER_ERROR,
+                "An installation is already running",
+            ));
+        }
+        let config = self
+            .config
+            .borrow()
+            .clone()
+            .ok_or(AppError::MissingConfig)?;
+        let cancel = Arc::new(AtomicBool::new(false));
+        let executor = TaskExecutor::with_config(config)
+            .resume(resume)
+            .cancel_flag(Arc::clone(&cancel));
+        let mut events = executor.subscribe();
+        let result = run_installation(executor)?;
+        *self.status.borrow_mut() = InstallStatus::Running {
+            task: None,
+            percentage: 0.0,
+        };
+        *self.cancel.borrow_mut() = Some(cancel);
+        let daemon = Rc::clone(self);
+
+        task::spawn_local(async move {
+            // the channel closes once the executor is dropped after the installation
+            loop {
+                match events.recv().await {
+                    Ok(event) => daemon.forward_event(event),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+            *daemon.status.borrow_mut() = match result.await {
+                Ok(Ok(_)) => InstallStatus::Completed,
+                Ok(Err(AppError::Cancelled)) => InstallStatus::Cancelled,
+                Ok(Err(e)) => InstallStatus::Failed {
+                    error: e.to_string(),
+                },
+                Err(_) => InstallStatus::Failed {
+                    error: "The installation thread stopped unexpectedly".into(),
+                },
+            };
+            daemon.cancel.borrow_mut().take();
+        });
+
+        Ok(())
+    }
+
+    fn cancel_install(&self) -> Result<(), RpcError> {
+        match &*self.cancel.borrow() {
+            Some(cancel) => {
+                cancel.store(true, Ordering::SeqCst);
+                Ok(())
+            }
+            None => Err(RpcError::new(SERVER_ERROR, "No installation is running")),
+        }
+    }
+
+    fn forward_event(&self, event: TaskEvent) {
+        if let InstallStatus::Running { task, percentage } = &mut *self.status.borrow_mut() {
+            match &event {
+                TaskEvent::TaskStarted { task: name } => *task = Some(name.to_owned()),
+                TaskEvent::Progress {
+                    percentage: value, ..
+                } => *percentage = *value,
+                _ => {}
+            }
+        }
+        let _ = self.events.send(event);
+    }
+
+    fn subscribe(&self, tx: mpsc::UnboundedSender<String>) -> JoinHandle<()> {
+        let mut events = self.events.subscribe();
+
+        task::spawn_local(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let notification = Notification {
+                    jsonrpc: "2.0",
+                    method: "event",
+                    params: &event,
+                };
+                let message = match serde_json::to_string(&notification) {
+                    Ok(message) => message + "\n",
+                    Err(_) => continue,
+                };
+                if tx.send(message).is_err() {
+                    break;
+                }
+            }
+        })
+    }
+}
+
+impl Response {
+    fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+
+        Self {
+            jsonrpc: "2.0",
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+/// Runs the installation on its own thread since the scripts block the thread they run on
+fn run_installation(executor: TaskExecutor) -> AppResult<oneshot::Receiver<AppResult<()>>> {
+    let (tx, rx) = oneshot::channel();
+    let runtime = runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    thread::Builder::new()
+        .name("installation".into())
+        .spawn(move || {
+            let result = runtime.block_on(executor.install_from_config());
+            let _ = tx.send(result);
+        })?;
+
+    Ok(rx)
+}
+
+/// Removes the socket of a previous daemon that didn't shut down cleanly.
+/// Other files and the sockets of running daemons are left alone
+async fn remove_stale_socket(socket: &Path) -> AppResult<()> {
+    let metadata = match fs::symlink_metadata(socket).await {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(AppError::NotASocket(socket.to_owned()));
+    }
+    if UnixStream::connect(socket).await.is_ok() {
+        return Err(AppError::DaemonRunning(socket.to_owned()));
+    }
+    fs::remove_file(socket).await?;
+
+    Ok(())
+}
+
+/// Returns the ID of the group with the given name or ID
+async fn group_id(group: &str) -> AppResult<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let groups = fs::read_to_string(GROUP_FILE).await?;
+
+    groups
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.first() == Some(&group))
+        .and_then(|fields| fields.get(2)?.parse().ok())
+        .ok_or_else(|| AppError::UnknownGroup(group.to_owned()))
+}
+
+fn parse_config(mut params: Value) -> Result<Config, RpcError> {
+    let config = params
+        .get_mut("config")
+        .map(Value::take)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing parameter config"))?;
+
+    Ok(Config::from_value(config)?)
+}
+
+fn validate(config: Config) -> Vec<ValidationError> {
+    let mut errors = config.validate();
+
+    if let Err(e) = TaskExecutor::with_config(config).plan() {
+        errors.push(ValidationError {
+            path: "$".into(),
+            message: e.to_string(),
+        });
+    }
+
+    errors
+}
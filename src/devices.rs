@@ -0,0 +1,328 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppResult,
+    utils::{PROCFS_PATH, SYSFS_PATH, UDEV_DATA_PATH},
+};
+
+/// sysfs reports sizes and offsets in 512 byte sectors regardless of the device
+const SECTOR_SIZE: u64 = 512;
+
+/// A disk that can be used as an installation target
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BlockDevice {
+    pub name: String,
+    /// The device file, e.g. `/dev/sda`
+    pub path: PathBuf,
+    /// The size in bytes
+    pub size: u64,
+    pub model: Option<String>,
+    pub removable: bool,
+    pub rotational: bool,
+    pub read_only: bool,
+    /// The existing partition table, e.g. `gpt` or `dos`
+    pub partition_table: Option<String>,
+    /// The filesystem if the disk is formatted without a partition table
+    pub filesystem: Option<String>,
+    pub partitions: Vec<BlockPartition>,
+}
+
+/// An existing partition of a [BlockDevice]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BlockPartition {
+    pub name: String,
+    pub path: PathBuf,
+    pub number: u32,
+    /// The offset on the disk in bytes
+    pub start: u64,
+    /// The size in bytes
+    pub size: u64,
+    pub filesystem: Option<String>,
+    pub label: Option<String>,
+    pub uuid: Option<String>,
+    pub partuuid: Option<String>,
+}
+
+/// Discovers block devices from sysfs, `/proc/partitions` and the udev database.
+/// The paths default to the ones of the running system and can be pointed at a fake tree
+#[derive(Clone, Debug)]
+pub struct DeviceScanner {
+    sysfs: PathBuf,
+    procfs: PathBuf,
+    udev_data: PathBuf,
+}
+
+impl Default for DeviceScanner {
+    fn default() -> Self {
+        Self {
+            sysfs: SYSFS_PATH.to_owned(),
+            procfs: PROCFS_PATH.to_owned(),
+            udev_data: UDEV_DATA_PATH.to_owned(),
+        }
+    }
+}
+
+impl DeviceScanner {
+    /// Reads `sys`, `proc` and `run/udev/data` inside the given root
+    pub fn with_root<P: AsRef<Path>>(root: P) -> Self {
+        let root = root.as_ref();
+
+        Self {
+            sysfs: root.join("sys"),
+            procfs: root.join("proc"),
+            udev_data: root.join("run/udev/data"),
+        }
+    }
+
+    /// Lists all physical disks with their partitions sorted by name
+    pub fn scan(&self) -> AppResult<Vec<BlockDevice>> {
+        let numbers = self.device_numbers()?;
+        let mut devices = Vec::new();
+
+        for entry in fs::read_dir(self.sysfs.join("block"))? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            let dir = self.sysfs.join("block").join(&name);
+
+            // virtual devices like loop, dm or zram devices aren't backed by hardware
+            if !dir.join("device").exists() {
+                continue;
+            }
+            let udev = numbers
+                .get(&name)
+                .map(|n| self.udev_properties(n))
+                .unwrap_or_default();
+            let mut partitions = numbers
+                .iter()
+                .filter(|(partition, _)| dir.join(partition).join("partition").exists())
+                .map(|(partition, number)| self.partition(&dir, partition, number))
+                .collect::<Vec<_>>();
+            partitions.sort_by_key(|p| p.number);
+
+            devices.push(BlockDevice {
+                path: device_path(&name),
+                size: read_number(&dir.join("size")).unwrap_or(0) * SECTOR_SIZE,
+                model: read_attribute(&dir.join("device/model")),
+                removable: read_flag(&dir.join("removable")),
+                rotational: read_flag(&dir.join("queue/rotational")),
+                read_only: read_flag(&dir.join("ro")),
+                partition_table: udev.get("ID_PART_TABLE_TYPE").cloned(),
+                filesystem: udev.get("ID_FS_TYPE").cloned(),
+                partitions,
+                name,
+            });
+        }
+        devices.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(devices)
+    }
+
+    /// Returns the disk with the given device file. Symlinks like `/dev/disk/by-id` are resolved
+    pub fn find(&self, path: &Path) -> AppResult<Option<BlockDevice>> {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+
+        Ok(self.scan()?.into_iter().find(|d| d.path == path))
+    }
+
+    fn partition(&self, disk_dir: &Path, name: &str, number: &str) -> BlockPartition {
+        let dir = disk_dir.join(name);
+        let udev = self.udev_properties(number);
+
+        BlockPartition {
+            name: name.to_owned(),
+            path: device_path(name),
+            number: read_number(&dir.join("partition")).unwrap_or(0) as u32,
+            start: read_number(&dir.join("start")).unwrap_or(0) * SECTOR_SIZE,
+            size: read_number(&dir.join("size")).unwrap_or(0) * SECTOR_SIZE,
+            filesystem: udev.get("ID_FS_TYPE").cloned(),
+            label: udev.get("ID_FS_LABEL").cloned(),
+            uuid: udev.get("ID_FS_UUID").cloned(),
+            partuuid: udev.get("ID_PART_ENTRY_UUID").cloned(),
+        }
+    }
+
+    /// Reads the `major:minor` numbers of all block devices from `/proc/partitions`
+    fn device_numbers(&self) -> AppResult<HashMap<String, String>> {
+        let partitions = fs::read_to_string(self.procfs.join("partitions"))?;
+
+        Ok(partitions
+            .lines()
+            .filter_map(
+                |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                    [major, minor, _, name] if major.parse::<u32>().is_ok() => {
+                        Some((name.to_owned(), format!("{major}:{minor}")))
+                    }
+                    _ => None,
+                },
+            )
+            .collect())
+    }
+
+    /// Reads the properties stored by udev for the device with the given `major:minor` numbers
+    fn udev_properties(&self, number: &str) -> HashMap<String, String> {
+        fs::read_to_string(self.udev_data.join(format!("b{number}")))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.strip_prefix("E:")?.split_once('='))
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect()
+    }
+}
+
+/// Returns the existing partition with the given device file
+pub fn find_partition<'a>(devices: &'a [BlockDevice], path: &Path) -> Option<&'a BlockPartition> {
+    devices
+        .iter()
+        .flat_map(|d| &d.partitions)
+        .find(|p| p.path == path)
+}
+
+/// Returns the device file for the kernel name. Slashes are encoded as `!` in sysfs
+fn device_path(name: &str) -> PathBuf {
+    Path::new("/dev").join(name.replace('!', "/"))
+}
+
+fn read_attribute(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+}
+
+fn read_number(path: &Path) -> Option<u64> {
+    read_attribute(path)?.parse().ok()
+}
+
+fn read_flag(path: &Path) -> bool {
+    read_number(path) == Some(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    /// Creates a tree with a partitioned SSD, an empty removable HDD and a loop device
+    fn fake_root() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        let root_path = root.path();
+        write(
+            root_path,
+            "proc/partitions",
+            "major minor  #blocks  name\n\n \
+             259        0  2097152 nvme0n1\n \
+             259        1   524288 nvme0n1p1\n \
+             259        2  1572864 nvme0n1p2\n   \
+             8        0  1048576 sda\n   \
+             7        0    10240 loop0\n",
+        );
+        write(root_path, "sys/block/nvme0n1/size", "4194304\n");
+        write(root_path, "sys/block/nvme0n1/device/model", "Fake SSD   \n");
+        write(root_path, "sys/block/nvme0n1/removable", "0\n");
+        write(root_path, "sys/block/nvme0n1/queue/rotational", "0\n");
+        write(root_path, "sys/block/nvme0n1/ro", "0\n");
+        // the second partition is listed first to check the sorting
+        write(root_path, "sys/block/nvme0n1/nvme0n1p2/partition", "2\n");
+        write(root_path, "sys/block/nvme0n1/nvme0n1p2/start", "1050624\n");
+        write(root_path, "sys/block/nvme0n1/nvme0n1p2/size", "3143680\n");
+        write(root_path, "sys/block/nvme0n1/nvme0n1p1/partition", "1\n");
+        write(root_path, "sys/block/nvme0n1/nvme0n1p1/start", "2048\n");
+        write(root_path, "sys/block/nvme0n1/nvme0n1p1/size", "1048576\n");
+        write(root_path, "sys/block/sda/size", "2097152\n");
+        write(root_path, "sys/block/sda/device/model", "\n");
+        write(root_path, "sys/block/sda/removable", "1\n");
+        write(root_path, "sys/block/sda/queue/rotational", "1\n");
+        write(root_path, "sys/block/sda/ro", "1\n");
+        write(root_path, "sys/block/loop0/size", "20480\n");
+        write(
+            root_path,
+            "run/udev/data/b259:0",
+            "E:ID_PART_TABLE_TYPE=gpt\n",
+        );
+        write(
+            root_path,
+            "run/udev/data/b259:1",
+            "S:disk/by-uuid/ABCD-1234\nE:ID_FS_TYPE=vfat\nE:ID_FS_UUID=ABCD-1234\nE:ID_FS_LABEL=EFI\n",
+        );
+        write(
+            root_path,
+            "run/udev/data/b259:2",
+            "E:ID_FS_TYPE=ext4\nE:ID_PART_ENTRY_UUID=0a1b2c3d-0000-4000-8000-000000000002\n",
+        );
+
+        root
+    }
+
+    #[test]
+    fn scans_physical_disks() {
+        let root = fake_root();
+        let devices = DeviceScanner::with_root(root.path()).scan().unwrap();
+
+        let names = devices.iter().map(|d| d.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["nvme0n1", "sda"]);
+
+        let ssd = &devices[0];
+        assert_eq!(ssd.path, Path::new("/dev/nvme0n1"));
+        assert_eq!(ssd.size, 4194304 * 512);
+        assert_eq!(ssd.model.as_deref(), Some("Fake SSD"));
+        assert!(!ssd.removable && !ssd.rotational && !ssd.read_only);
+        assert_eq!(ssd.partition_table.as_deref(), Some("gpt"));
+
+        let hdd = &devices[1];
+        assert_eq!(hdd.model, None);
+        assert!(hdd.removable && hdd.rotational && hdd.read_only);
+        assert_eq!(hdd.partition_table, None);
+        assert!(hdd.partitions.is_empty());
+    }
+
+    #[test]
+    fn scans_partitions() {
+        let root = fake_root();
+        let devices = DeviceScanner::with_root(root.path()).scan().unwrap();
+        let partitions = &devices[0].partitions;
+
+        let numbers = partitions.iter().map(|p| p.number).collect::<Vec<_>>();
+        assert_eq!(numbers, [1, 2]);
+
+        let esp = &partitions[0];
+        assert_eq!(esp.path, Path::new("/dev/nvme0n1p1"));
+        assert_eq!(esp.start, 2048 * 512);
+        assert_eq!(esp.size, 1048576 * 512);
+        assert_eq!(esp.filesystem.as_deref(), Some("vfat"));
+        assert_eq!(esp.label.as_deref(), Some("EFI"));
+        assert_eq!(esp.uuid.as_deref(), Some("ABCD-1234"));
+
+        let root_partition = &partitions[1];
+        assert_eq!(root_partition.filesystem.as_deref(), Some("ext4"));
+        assert_eq!(root_partition.label, None);
+        assert_eq!(
+            root_partition.partuuid.as_deref(),
+            Some("0a1b2c3d-0000-4000-8000-000000000002")
+        );
+    }
+
+    #[test]
+    fn finds_partitions_by_path() {
+        let root = fake_root();
+        let devices = DeviceScanner::with_root(root.path()).scan().unwrap();
+
+        let partition = find_partition(&devices, Path::new("/dev/nvme0n1p2")).unwrap();
+        assert_eq!(partition.number, 2);
+        assert!(find_partition(&devices, Path::new("/dev/sda1")).is_none());
+    }
+
+    #[test]
+    fn decodes_slashes_in_device_names() {
+        assert_eq!(device_path("cciss!c0d0"), Path::new("/dev/cciss/c0d0"));
+    }
+}
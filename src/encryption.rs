@@ -0,0 +1,146 @@
+use std::path::{Path, PathBuf};
+
+use embed_nu::rusty_value::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    secrets::Secret,
+    validation::{Validate, Validator},
+};
+
+const DEFAULT_CIPHER: &str = "aes-xts-plain64";
+const DEFAULT_KEY_SIZE: u32 = 512;
+const ROOT_MAPPER_NAME: &str = "cryptroot";
+
+/// LUKS2 encryption of a partition, RAID array or logical volume
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct EncryptionConfig {
+    /// The name of the unlocked device in `/dev/mapper`.
+    /// Defaults to `cryptroot` for the root filesystem and `crypt-<device>` otherwise
+    pub name: Option<String>,
+    #[serde(default = "default_cipher")]
+    pub cipher: String,
+    /// The key size in bits
+    #[serde(default = "default_key_size")]
+    pub key_size: u32,
+    #[serde(default)]
+    pub pbkdf: Pbkdf,
+    /// The memory used by argon2 in KiB
+    pub pbkdf_memory: Option<u32>,
+    /// The time spent deriving the key in milliseconds
+    pub iter_time: Option<u32>,
+    /// The passphrase used to unlock the partition. Either this or a keyfile is required
+    pub passphrase: Option<Secret>,
+    /// The path of a keyfile on the installation medium used to unlock the partition
+    pub keyfile: Option<PathBuf>,
+    /// Enrolls the TPM2 chip for unlocking. This is a placeholder the scripts can act on
+    #[serde(default)]
+    pub tpm2: bool,
+}
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, RustyValue, JsonSchema,
+)]
+pub enum Pbkdf {
+    #[default]
+    Argon2id,
+    Argon2i,
+    Pbkdf2,
+}
+
+/// An encrypted device together with the device it is unlocked to
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue)]
+pub struct EncryptedDevice {
+    /// The encrypted device, e.g. `/dev/sda2`
+    pub device: PathBuf,
+    pub name: String,
+    /// The unlocked device, e.g. `/dev/mapper/cryptroot`
+    pub mapper: PathBuf,
+    /// The UUID of the LUKS header if it is known
+    pub uuid: Option<String>,
+    pub tpm2: bool,
+}
+
+fn default_cipher() -> String {
+    DEFAULT_CIPHER.into()
+}
+
+fn default_key_size() -> u32 {
+    DEFAULT_KEY_SIZE
+}
+
+impl EncryptionConfig {
+    /// Returns the mapper name of the device. `is_root` is set if it holds the root filesystem
+    pub fn mapper_name(&self, device: &Path, is_root: bool) -> String {
+        match &self.name {
+            Some(name) => name.to_owned(),
+            None if is_root => ROOT_MAPPER_NAME.into(),
+            None => {
+                let device = device
+                    .strip_prefix("/dev")
+                    .unwrap_or(device)
+                    .to_string_lossy()
+                    .replace('/', "-");
+                format!("crypt-{device}")
+            }
+        }
+    }
+
+    /// Resolves the unlocked device for the given encrypted device
+    pub fn device(&self, device: &Path, is_root: bool) -> EncryptedDevice {
+        let name = self.mapper_name(device, is_root);
+
+        EncryptedDevice {
+            device: device.to_owned(),
+            mapper: Path::new("/dev/mapper").join(&name),
+            name,
+            uuid: None,
+            tpm2: self.tpm2,
+        }
+    }
+
+    pub(crate) fn redact(&mut self) {
+        if self.passphrase.is_some() {
+            self.passphrase = Some(Secret::Redacted);
+        }
+    }
+}
+
+impl Validate for EncryptionConfig {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        if let Some(name) = &self.name {
+            if name.is_empty() || name.contains(|c: char| c == '/' || c.is_whitespace()) {
+                validator.error(
+                    format!("{path}.name"),
+                    "the name must not be empty or contain slashes or whitespace",
+                );
+            }
+        }
+        if self.cipher.is_empty() {
+            validator.error(format!("{path}.cipher"), "the cipher must not be empty");
+        }
+        if self.key_size == 0 || !self.key_size.is_multiple_of(8) {
+            validator.error(
+                format!("{path}.key_size"),
+                "the key size must be a positive multiple of 8",
+            );
+        }
+        if self.pbkdf == Pbkdf::Pbkdf2 && self.pbkdf_memory.is_some() {
+            validator.error(
+                format!("{path}.pbkdf_memory"),
+                "the memory cost is only supported by argon2",
+            );
+        }
+        match (&self.passphrase, &self.keyfile) {
+            (Some(passphrase), None) => {
+                validator.validate(&format!("{path}.passphrase"), passphrase);
+            }
+            (None, Some(keyfile)) if !keyfile.is_absolute() => {
+                validator.error(format!("{path}.keyfile"), "the keyfile must be absolute");
+            }
+            (None, Some(_)) => {}
+            _ => validator.error(path, "either a passphrase or a keyfile is required"),
+        }
+    }
+}
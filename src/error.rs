@@ -2,6 +2,8 @@ use std::{io, path::PathBuf};
 
 use thiserror::Error;
 
+use crate::validation::ValidationError;
+
 pub type AppResult<T> = std::result::Result<T, AppError>;
 
 #[derive(Error, Debug)]
@@ -10,7 +12,7 @@ pub enum AppError {
     ScriptNotFound(PathBuf),
 
     #[error("Nu error {0}")]
-    NuError(#[from] embed_nu::Error),
+    NuError(Box<embed_nu::Error>),
 
     #[error("Could not find the main mehod in the script file {0}")]
     MissingMain(PathBuf),
@@ -21,9 +23,83 @@ pub enum AppError {
     #[error("Missing config")]
     MissingConfig,
 
+    #[error("Invalid config: {}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))]
+    InvalidConfig(Vec<ValidationError>),
+
+    #[error("Invalid task manifest {0}: {1}")]
+    InvalidManifest(PathBuf, serde_json::Error),
+
+    #[error("Invalid arguments for the task {0}: {1}")]
+    InvalidTaskArgs(String, String),
+
+    #[error("The task {0} is defined more than once")]
+    DuplicateTask(String),
+
+    #[error("The task {0} depends on the unknown task {1}")]
+    UnknownDependency(String, String),
+
+    #[error("The task dependencies contain a cycle: {}", .0.join(" -> "))]
+    DependencyCycle(Vec<String>),
+
+    #[error("The installation was cancelled")]
+    Cancelled,
+
+    #[error("The config of the already completed task {0} has changed. Refusing to resume")]
+    ConfigChanged(String),
+
+    #[error("Could not determine the format of the config {0}. Use the --format flag")]
+    UnknownConfigFormat(PathBuf),
+
+    #[error("The config {0} has an invalid extends value. Expected a path or a list of paths")]
+    InvalidExtends(PathBuf),
+
+    #[error("The extended configs contain a cycle: {}", .0.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(" -> "))]
+    ExtendsCycle(Vec<PathBuf>),
+
+    #[error("Invalid config value at {path}{}: {message}", .layer.as_ref().map(|l| format!(" set in {l}")).unwrap_or_default())]
+    InvalidConfigValue {
+        path: String,
+        /// The file or override the value is set in
+        layer: Option<String>,
+        message: String,
+    },
+
+    #[error("Invalid override {0}: {1}")]
+    InvalidOverride(String, String),
+
+    #[error("Could not find the block device {0}")]
+    DeviceNotFound(PathBuf),
+
+    #[error("The partition layout doesn't fit the device: {0}")]
+    InvalidPartitionLayout(String),
+
+    #[error("{0} exists and is not a socket. Refusing to replace it")]
+    NotASocket(PathBuf),
+
+    #[error("Another daemon is already listening on {0}")]
+    DaemonRunning(PathBuf),
+
+    #[error("Could not find the group {0}")]
+    UnknownGroup(String),
+
     #[error("IO Error: {0}")]
     Io(#[from] io::Error),
 
     #[error("JSON deserialization error {0}")]
     JSON(#[from] serde_json::Error),
+
+    #[error("TOML deserialization error {0}")]
+    TOML(#[from] toml::de::Error),
+
+    #[error("TOML serialization error {0}")]
+    TOMLSerialization(#[from] toml::ser::Error),
+
+    #[error("YAML error {0}")]
+    YAML(#[from] serde_yaml::Error),
+}
+
+impl From<embed_nu::Error> for AppError {
+    fn from(e: embed_nu::Error) -> Self {
+        Self::NuError(Box::new(e))
+    }
 }
@@ -0,0 +1,59 @@
+use serde::Serialize;
+
+use crate::scripting::loader::HookType;
+
+/// An event emitted by the task executor while executing tasks
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TaskEvent {
+    TaskStarted {
+        task: String,
+    },
+    HookStarted {
+        task: String,
+        hook: HookType,
+    },
+    /// A line of output of a script or hook. Emitted while the script is running
+    ScriptOutput {
+        task: String,
+        line: String,
+    },
+    TaskFinished {
+        task: String,
+    },
+    TaskFailed {
+        task: String,
+        error: String,
+    },
+    /// A problem that doesn't stop the task, e.g. a shell missing in the installed system
+    TaskWarning {
+        task: String,
+        message: String,
+    },
+    TaskSkipped {
+        task: String,
+        reason: String,
+    },
+    /// The overall progress of an installation
+    Progress {
+        completed: usize,
+        total: usize,
+        percentage: f32,
+    },
+}
+
+impl TaskEvent {
+    pub(crate) fn progress(completed: usize, total: usize) -> Self {
+        let percentage = if total == 0 {
+            100.0
+        } else {
+            completed as f32 / total as f32 * 100.0
+        };
+
+        Self::Progress {
+            completed,
+            total,
+            percentage,
+        }
+    }
+}
@@ -0,0 +1,393 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use embed_nu::rusty_value::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    devices::{find_partition, BlockDevice},
+    partitioning::{PartitionPlan, PlanStep},
+    tasks::FileSystem,
+};
+
+/// How the filesystems are referred to in the generated fstab.
+/// Falls back to the UUID and then the device path if the identifier isn't available
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, RustyValue, JsonSchema,
+)]
+pub enum FstabIdentifier {
+    #[default]
+    Uuid,
+    PartUuid,
+    Label,
+    Device,
+}
+
+/// The `/etc/fstab` of the installed system
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Fstab {
+    pub entries: Vec<FstabEntry>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FstabEntry {
+    /// The device the entry was created for, e.g. `/dev/sda2`
+    pub device: PathBuf,
+    /// The identifier of the filesystem, e.g. `UUID=...`
+    pub spec: String,
+    /// The mountpoint or `none` for swap
+    pub file: String,
+    pub vfstype: String,
+    pub options: Vec<String>,
+    pub dump: u8,
+    pub pass: u8,
+}
+
+/// What is known about the filesystem on a device
+#[derive(Default)]
+struct DeviceInfo {
+    filesystem: Option<String>,
+    uuid: Option<String>,
+    partuuid: Option<String>,
+    label: Option<String>,
+}
+
+impl Fstab {
+    /// Generates the entries for all mounts and swap devices of the plan in mount order.
+    /// Filesystems that aren't created by the plan are looked up in the existing devices
+    pub fn new(
+        plan: &PartitionPlan,
+        identifier: FstabIdentifier,
+        existing: &[BlockDevice],
+    ) -> Self {
+        let mut entries = Vec::new();
+
+        for step in &plan.steps {
+            match step {
+                PlanStep::Mount {
+                    device,
+                    mountpoint,
+                    subvolume,
+                    options,
+                } => {
+                    let info = device_info(plan, existing, device);
+                    let vfstype = info.filesystem.clone().unwrap_or_else(|| "auto".into());
+                    let options = subvolume
+                        .iter()
+                        .map(|s| format!("subvol=/{s}"))
+                        .chain(options.iter().cloned())
+                        .collect();
+
+                    entries.push(FstabEntry {
+                        device: device.to_owned(),
+                        spec: spec(identifier, device, &info),
+                        file: mountpoint.to_string_lossy().into_owned(),
+                        pass: pass(mountpoint, &vfstype),
+                        vfstype,
+                        options,
+                        dump: 0,
+                    });
+                }
+                PlanStep::EnableSwap { device } => {
+                    let info = device_info(plan, existing, device);
+
+                    entries.push(FstabEntry {
+                        device: device.to_owned(),
+                        spec: spec(identifier, device, &info),
+                        file: "none".into(),
+                        vfstype: "swap".into(),
+                        options: Vec::new(),
+                        dump: 0,
+                        pass: 0,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        Self { entries }
+    }
+}
+
+impl fmt::Display for Fstab {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# /etc/fstab generated by tourmaline")?;
+        writeln!(f, "# <file system> <dir> <type> <options> <dump> <pass>")?;
+
+        for entry in &self.entries {
+            let options = if entry.options.is_empty() {
+                "defaults".into()
+            } else {
+                entry.options.join(",")
+            };
+            writeln!(f)?;
+            writeln!(f, "# {}", entry.device.display())?;
+            writeln!(
+                f,
+                "{}\t{}\t{}\t{}\t{} {}",
+                entry.spec, entry.file, entry.vfstype, options, entry.dump, entry.pass
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl RustyValue for Fstab {
+    fn into_rusty_value(self) -> Value {
+        Value::Primitive(Primitive::String(self.to_string()))
+    }
+}
+
+impl FileSystem {
+    /// Returns the type of the filesystem as used by mount and fstab
+    pub fn fs_type(&self) -> &'static str {
+        match self {
+            FileSystem::VFAT | FileSystem::FAT => "vfat",
+            FileSystem::MSDOS => "msdos",
+            FileSystem::BFS => "bfs",
+            FileSystem::CramFS => "cramfs",
+            FileSystem::Ext2 => "ext2",
+            FileSystem::Ext3 => "ext3",
+            FileSystem::Ext4 => "ext4",
+            FileSystem::XFS => "xfs",
+            FileSystem::BTRFS => "btrfs",
+            FileSystem::Minix => "minix",
+            FileSystem::F2FS => "f2fs",
+            FileSystem::Swap => "swap",
+        }
+    }
+}
+
+fn device_info(plan: &PartitionPlan, existing: &[BlockDevice], device: &Path) -> DeviceInfo {
+    let mut info = DeviceInfo::default();
+
+    for step in &plan.steps {
+        match step {
+            PlanStep::Format {
+                device: formatted,
+                filesystem,
+                label,
+                uuid,
+            } if formatted == device => {
+                info.filesystem = Some(filesystem.fs_type().into());
+                info.label = label.clone();
+                info.uuid = uuid.clone();
+            }
+            PlanStep::PartitionDisk { partitions, .. } => {
+                if let Some(partition) = partitions.iter().find(|p| p.device == device) {
+                    info.partuuid = Some(partition.uuid.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+    // the filesystem is reused if the plan doesn't format the device
+    if info.filesystem.is_none() {
+        if let Some(partition) = find_partition(existing, device) {
+            info.filesystem = partition.filesystem.clone();
+            info.uuid = partition.uuid.clone();
+            info.partuuid = partition.partuuid.clone();
+            info.label = partition.label.clone();
+        }
+    }
+
+    info
+}
+
+fn spec(identifier: FstabIdentifier, device: &Path, info: &DeviceInfo) -> String {
+    let preferred = match identifier {
+        FstabIdentifier::PartUuid => info.partuuid.as_ref().map(|u| format!("PARTUUID={u}")),
+        // whitespace would separate the fields of the entry
+        FstabIdentifier::Label => info
+            .label
+            .as_ref()
+            .map(|l| format!("LABEL={}", l.replace(' ', "\\040"))),
+        FstabIdentifier::Uuid | FstabIdentifier::Device => None,
+    };
+    let uuid = || match identifier {
+        FstabIdentifier::Device => None,
+        _ => info.uuid.as_ref().map(|u| format!("UUID={u}")),
+    };
+
+    preferred
+        .or_else(uuid)
+        .unwrap_or_else(|| device.to_string_lossy().into_owned())
+}
+
+/// Returns the fsck pass. The root filesystem is checked first and
+/// filesystems without a meaningful fsck like btrfs and xfs are skipped
+fn pass(mountpoint: &Path, vfstype: &str) -> u8 {
+    match vfstype {
+        "btrfs" | "xfs" | "auto" | "swap" => 0,
+        _ if mountpoint == Path::new("/") => 1,
+        _ => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{devices::BlockPartition, partitioning::PlannedPartition, tasks::PartitionTable};
+
+    fn partition(number: u32) -> PlannedPartition {
+        PlannedPartition {
+            number,
+            device: PathBuf::from(format!("/dev/sda{number}")),
+            start: 0,
+            size: 0,
+            partition_type: String::new(),
+            name: None,
+            uuid: format!("partuuid-{number}"),
+        }
+    }
+
+    fn format(number: u32, filesystem: FileSystem, label: Option<&str>) -> PlanStep {
+        PlanStep::Format {
+            device: PathBuf::from(format!("/dev/sda{number}")),
+            filesystem,
+            label: label.map(String::from),
+            uuid: Some(format!("uuid-{number}")),
+        }
+    }
+
+    fn mount(device: &str, mountpoint: &str, subvolume: Option<&str>) -> PlanStep {
+        PlanStep::Mount {
+            device: device.into(),
+            mountpoint: mountpoint.into(),
+            subvolume: subvolume.map(String::from),
+            options: vec!["noatime".into()],
+        }
+    }
+
+    fn plan() -> PartitionPlan {
+        PartitionPlan {
+            steps: vec![
+                PlanStep::PartitionDisk {
+                    device: "/dev/sda".into(),
+                    table: PartitionTable::Gpt,
+                    disk_id: String::new(),
+                    partitions: (1..=4).map(partition).collect(),
+                },
+                format(1, FileSystem::VFAT, Some("EFI SYSTEM")),
+                format(2, FileSystem::BTRFS, Some("root")),
+                format(3, FileSystem::Swap, None),
+                format(4, FileSystem::Ext4, Some("home")),
+                mount("/dev/sda2", "/", Some("@")),
+                mount("/dev/sda4", "/home", None),
+                mount("/dev/sda1", "/boot/efi", None),
+                PlanStep::EnableSwap {
+                    device: "/dev/sda3".into(),
+                },
+            ],
+        }
+    }
+
+    fn specs(identifier: FstabIdentifier) -> Vec<String> {
+        Fstab::new(&plan(), identifier, &[])
+            .entries
+            .into_iter()
+            .map(|e| e.spec)
+            .collect()
+    }
+
+    #[test]
+    fn refers_to_filesystems_by_the_identifier() {
+        assert_eq!(
+            specs(FstabIdentifier::Uuid),
+            ["UUID=uuid-2", "UUID=uuid-4", "UUID=uuid-1", "UUID=uuid-3"]
+        );
+        assert_eq!(
+            specs(FstabIdentifier::PartUuid),
+            [
+                "PARTUUID=partuuid-2",
+                "PARTUUID=partuuid-4",
+                "PARTUUID=partuuid-1",
+                "PARTUUID=partuuid-3"
+            ]
+        );
+        assert_eq!(
+            specs(FstabIdentifier::Label),
+            [
+                "LABEL=root",
+                "LABEL=home",
+                "LABEL=EFI\\040SYSTEM",
+                "UUID=uuid-3"
+            ]
+        );
+        assert_eq!(
+            specs(FstabIdentifier::Device),
+            ["/dev/sda2", "/dev/sda4", "/dev/sda1", "/dev/sda3"]
+        );
+    }
+
+    #[test]
+    fn mounts_btrfs_subvolumes() {
+        let root = &Fstab::new(&plan(), FstabIdentifier::Uuid, &[]).entries[0];
+
+        assert_eq!(root.file, "/");
+        assert_eq!(root.vfstype, "btrfs");
+        assert_eq!(root.options, ["subvol=/@", "noatime"]);
+    }
+
+    #[test]
+    fn adds_swap_without_a_mountpoint() {
+        let swap = &Fstab::new(&plan(), FstabIdentifier::Uuid, &[]).entries[3];
+
+        assert_eq!(swap.file, "none");
+        assert_eq!(swap.vfstype, "swap");
+        assert_eq!(swap.options, Vec::<String>::new());
+        assert_eq!(swap.pass, 0);
+    }
+
+    #[test]
+    fn checks_the_root_filesystem_first() {
+        let passes = Fstab::new(&plan(), FstabIdentifier::Uuid, &[])
+            .entries
+            .iter()
+            .map(|e| e.pass)
+            .collect::<Vec<_>>();
+
+        assert_eq!(passes, [0, 2, 2, 0]);
+        assert_eq!(pass(Path::new("/"), "ext4"), 1);
+        assert_eq!(pass(Path::new("/"), "xfs"), 0);
+        assert_eq!(pass(Path::new("/data"), "auto"), 0);
+    }
+
+    #[test]
+    fn reuses_existing_filesystems() {
+        let plan = PartitionPlan {
+            steps: vec![mount("/dev/sdb1", "/data", None)],
+        };
+        let existing = BlockDevice {
+            name: "sdb".into(),
+            path: "/dev/sdb".into(),
+            size: 0,
+            model: None,
+            removable: false,
+            rotational: false,
+            read_only: false,
+            partition_table: Some("gpt".into()),
+            filesystem: None,
+            partitions: vec![BlockPartition {
+                name: "sdb1".into(),
+                path: "/dev/sdb1".into(),
+                number: 1,
+                start: 0,
+                size: 0,
+                filesystem: Some("ext4".into()),
+                label: None,
+                uuid: Some("existing".into()),
+                partuuid: None,
+            }],
+        };
+        let entry = &Fstab::new(&plan, FstabIdentifier::Uuid, &[existing]).entries[0];
+
+        assert_eq!(entry.spec, "UUID=existing");
+        assert_eq!(entry.vfstype, "ext4");
+        assert_eq!(entry.pass, 2);
+    }
+}
@@ -0,0 +1,151 @@
+use crate::{
+    error::{AppError, AppResult},
+    plan::TaskStep,
+};
+
+/// Sorts the given steps so that every task is executed after its dependencies
+/// and before its dependents.
+/// Steps that don't depend on each other keep their relative order.
+pub(crate) fn sort_steps(steps: Vec<TaskStep>) -> AppResult<Vec<TaskStep>> {
+    let names = steps
+        .iter()
+        .map(|s| s.files().name().to_owned())
+        .collect::<Vec<_>>();
+    let mut dependencies = steps
+        .iter()
+        .zip(names.iter())
+        .map(|(step, name)| {
+            step.files()
+                .dependencies()
+                .iter()
+                .map(|dep| {
+                    names
+                        .iter()
+                        .position(|n| n == dep)
+                        .ok_or_else(|| AppError::UnknownDependency(name.to_owned(), dep.to_owned()))
+                })
+                .collect::<AppResult<Vec<_>>>()
+        })
+        .collect::<AppResult<Vec<_>>>()?;
+
+    for (i, step) in steps.iter().enumerate() {
+        for dependent in step.files().dependents() {
+            let j = names.iter().position(|n| n == dependent).ok_or_else(|| {
+                AppError::UnknownDependency(names[i].clone(), dependent.to_owned())
+            })?;
+            dependencies[j].push(i);
+        }
+    }
+
+    let mut sorted = vec![false; steps.len()];
+    let mut order = Vec::with_capacity(steps.len());
+
+    while order.len() < steps.len() {
+        let next =
+            (0..steps.len()).find(|&i| !sorted[i] && dependencies[i].iter().all(|&d| sorted[d]));
+
+        if let Some(i) = next {
+            sorted[i] = true;
+            order.push(i);
+        } else {
+            let cycle = find_cycle(&dependencies, &sorted);
+            return Err(AppError::DependencyCycle(
+                cycle.into_iter().map(|i| names[i].clone()).collect(),
+            ));
+        }
+    }
+    let mut steps = steps.into_iter().map(Some).collect::<Vec<_>>();
+
+    Ok(order.into_iter().filter_map(|i| steps[i].take()).collect())
+}
+
+/// Follows the unsorted dependencies of the remaining steps until
+/// a step is visited twice. Every remaining step has at least one unsorted
+/// dependency, so this always ends in a cycle.
+fn find_cycle(dependencies: &[Vec<usize>], sorted: &[bool]) -> Vec<usize> {
+    let mut path = Vec::new();
+    let mut current = (0..sorted.len()).find(|&i| !sorted[i]).unwrap_or_default();
+
+    while !path.contains(&current) {
+        path.push(current);
+        current = dependencies[current]
+            .iter()
+            .copied()
+            .find(|&d| !sorted[d])
+            .unwrap_or(current);
+    }
+    let start = path.iter().position(|&i| i == current).unwrap_or_default();
+    let mut cycle = path.split_off(start);
+    cycle.push(current);
+
+    cycle
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{plan::SkipReason, tasks::TaskManifest};
+
+    fn step(name: &str, after: &[&str], before: &[&str]) -> TaskStep {
+        let mut manifest: TaskManifest =
+            serde_json::from_value(json!({ "after": after, "before": before })).unwrap();
+        manifest.name = name.into();
+
+        TaskStep::Skip(manifest.files(), SkipReason::Disabled("test"))
+    }
+
+    fn names(steps: Vec<TaskStep>) -> Vec<String> {
+        steps.iter().map(|s| s.files().name().to_owned()).collect()
+    }
+
+    #[test]
+    fn orders_steps_by_after_and_before() {
+        let steps = vec![
+            step("c", &["b"], &[]),
+            step("a", &[], &[]),
+            step("b", &[], &[]),
+            step("d", &[], &["a"]),
+        ];
+
+        assert_eq!(names(sort_steps(steps).unwrap()), ["b", "c", "d", "a"]);
+    }
+
+    #[test]
+    fn keeps_the_order_of_independent_steps() {
+        let steps = vec![step("b", &[], &[]), step("a", &[], &[])];
+
+        assert_eq!(names(sort_steps(steps).unwrap()), ["b", "a"]);
+    }
+
+    #[test]
+    fn names_the_tasks_of_a_cycle() {
+        let steps = vec![
+            step("a", &[], &[]),
+            step("b", &["d"], &[]),
+            step("c", &["b"], &[]),
+            step("d", &["c"], &[]),
+        ];
+
+        match sort_steps(steps) {
+            Err(AppError::DependencyCycle(cycle)) => assert_eq!(cycle, ["b", "d", "c", "b"]),
+            _ => panic!("expected a dependency cycle"),
+        }
+    }
+
+    #[test]
+    fn reports_missing_dependencies() {
+        for steps in [
+            vec![step("a", &["missing"], &[])],
+            vec![step("a", &[], &["missing"])],
+        ] {
+            match sort_steps(steps) {
+                Err(AppError::UnknownDependency(task, dependency)) => {
+                    assert_eq!((task.as_str(), dependency.as_str()), ("a", "missing"))
+                }
+                _ => panic!("expected an unknown dependency"),
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+
+use crate::{error::AppResult, scripting::loader::HookType};
+
+/// A journal of the tasks executed during an installation
+/// that gets persisted to disk after every change
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Journal {
+    #[serde(skip)]
+    path: PathBuf,
+    /// Mixed into the IDs of the created partitions and filesystems so they differ
+    /// between installations. Empty for journals written before it was introduced
+    #[serde(default)]
+    pub seed: String,
+    pub entries: Vec<JournalEntry>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub task: String,
+    /// The hash of the arguments the task was executed with
+    pub config_hash: String,
+    pub status: TaskStatus,
+    pub pre_hook: HookStatus,
+    pub post_hook: HookStatus,
+    /// Seconds since the unix epoch
+    pub started_at: u64,
+    /// Seconds since the unix epoch
+    pub finished_at: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TaskStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HookStatus {
+    Pending,
+    Missing,
+    Completed,
+    Failed,
+}
+
+impl Journal {
+    /// Creates a new empty journal that is written to the given path
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            seed: generate_seed(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Loads the journal from the given path.
+    /// Returns an empty journal if the file doesn't exist
+    pub async fn load(path: PathBuf) -> AppResult<Self> {
+        if !path.exists() {
+            return Ok(Self::new(path));
+        }
+        let contents = fs::read_to_string(&path).await?;
+        let mut journal: Journal = serde_json::from_str(&contents)?;
+        journal.path = path;
+
+        Ok(journal)
+    }
+
+    /// Writes the journal to its file
+    pub async fn save(&self) -> AppResult<()> {
+        if let Some(parent) = self.path.parent().filter(|p| !p.exists()) {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(self)?).await?;
+
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the entry of the given task if it has been completed
+    pub fn completed(&self, task: &str) -> Option<&JournalEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|e| e.task == task)
+            .filter(|e| e.status == TaskStatus::Completed)
+    }
+
+    /// Records the start of a task
+    pub fn start(&mut self, task: &str, config_hash: String) {
+        self.entries.retain(|e| e.task != task);
+        self.entries.push(JournalEntry {
+            task: task.to_owned(),
+            config_hash,
+            status: TaskStatus::Running,
+            pre_hook: HookStatus::Pending,
+            post_hook: HookStatus::Pending,
+            started_at: now(),
+            finished_at: None,
+        });
+    }
+
+    /// Records the status of one of the hooks of a running task
+    pub fn hook(&mut self, task: &str, hook_type: HookType, status: HookStatus) {
+        if let Some(entry) = self.entry_mut(task) {
+            match hook_type {
+                HookType::Pre => entry.pre_hook = status,
+                HookType::Post => entry.post_hook = status,
+            }
+        }
+    }
+
+    /// Records the end of a task
+    pub fn finish(&mut self, task: &str, status: TaskStatus) {
+        if let Some(entry) = self.entry_mut(task) {
+            entry.status = status;
+            entry.finished_at = Some(now());
+        }
+    }
+
+    fn entry_mut(&mut self, task: &str) -> Option<&mut JournalEntry> {
+        self.entries.iter_mut().rev().find(|e| e.task == task)
+    }
+}
+
+/// Hashes the arguments of a task to detect config changes between runs
+pub fn config_hash(args: &serde_json::Value) -> String {
+    format!("{:x}", Sha256::digest(args.to_string()))
+}
+
+/// Returns 16 random bytes as hex. Falls back to hashing the time and process ID
+/// if the random device can't be read
+pub fn generate_seed() -> String {
+    let mut bytes = [0; 16];
+    let random = File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut bytes));
+
+    if random.is_err() {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let hash = Sha256::digest(format!("{nanos}:{}", process::id()));
+        bytes.copy_from_slice(&hash[..16]);
+    }
+
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -1,16 +1,38 @@
 use config::Config;
 use error::{AppError, AppResult};
+use events::TaskEvent;
+use fstab::Fstab;
+use journal::{config_hash, generate_seed, HookStatus, Journal, TaskStatus};
+use plan::{task_steps, InstallPlan, PlannedTask, TaskStep};
 use scripting::{
     loader::{HookType, ScriptLoader},
-    script::{NuScript, Script},
+    script::{NuScript, Script, TaskArgs},
+};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
 };
 use tasks::*;
+use tokio::sync::{broadcast, Mutex};
 
+pub mod btrfs;
 pub mod config;
+pub mod daemon;
+pub mod devices;
+pub mod encryption;
 pub mod error;
+pub mod events;
+pub mod fstab;
+pub(crate) mod graph;
+pub mod journal;
+pub mod locales;
+pub mod partitioning;
+pub mod plan;
 pub(crate) mod scripting;
+pub mod secrets;
 pub mod tasks;
 pub(crate) mod utils;
+pub mod validation;
 pub use utils::generate_script_files;
 
 macro_rules! tasks {
@@ -24,9 +46,20 @@ macro_rules! tasks {
     }
 }
 
+/// The number of events a subscriber can lag behind before missing events
+pub(crate) const EVENT_CAPACITY: usize = 1024;
+
 pub struct TaskExecutor {
     config: Option<Config>,
     loader: ScriptLoader,
+    journal: Mutex<Journal>,
+    /// The config passed to all scripts. Its passwords are hashed once when the installation starts
+    script_config: Mutex<Option<Config>>,
+    /// The fstab passed to all scripts. Generated once when the installation starts
+    fstab: Mutex<Option<Fstab>>,
+    resume: bool,
+    cancelled: Arc<AtomicBool>,
+    events: broadcast::Sender<TaskEvent>,
 }
 
 impl TaskExecutor {
@@ -34,10 +67,31 @@ impl TaskExecutor {
     pub fn with_config(config: Config) -> Self {
         Self {
             config: Some(config),
-            loader: ScriptLoader::new(),
+            ..Default::default()
         }
     }
 
+    /// Skips the tasks that have already been completed with the same config
+    /// according to the journal of a previous installation
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+
+        self
+    }
+
+    /// Stops the installation before the next task once the flag is set.
+    /// A running script can't be interrupted
+    pub fn cancel_flag(mut self, cancelled: Arc<AtomicBool>) -> Self {
+        self.cancelled = cancelled;
+
+        self
+    }
+
+    /// Subscribes to the events emitted while executing tasks
+    pub fn subscribe(&self) -> broadcast::Receiver<TaskEvent> {
+        self.events.subscribe()
+    }
+
     tasks!(
         setup_users => SetupUsersScript,
         configure_network => ConfigureNetworkScript,
@@ -59,60 +113,220 @@ impl TaskExecutor {
     #[tracing::instrument(level = "trace", skip(self))]
     pub async fn install_from_config(&self) -> AppResult<()> {
         let config = self.config.clone().ok_or(AppError::MissingConfig)?;
-        self.create_partitions(config.partitions).await?;
-        self.install_base(()).await?;
-        self.install_kernels(config.kernels).await?;
-        self.install_bootloader(config.bootloader).await?;
-        self.configure_locale(config.locale).await?;
-        self.configure_network(config.network).await?;
-
-        if config.enable_zramd {
-            self.install_zramd(()).await?;
-        }
-        if config.enable_timeshift {
-            self.install_timeshift(()).await?;
-        }
-        if config.enable_flatpak {
-            self.install_flatpak(()).await?;
+        let errors = config.validate();
+
+        if !errors.is_empty() {
+            return Err(AppError::InvalidConfig(errors));
         }
-        self.setup_users(config.users).await?;
-        self.setup_root_user(config.root_user).await?;
-        self.install_desktop(config.desktop).await?;
-        self.install_extra_packages(config.extra_packages).await?;
+        self.install(config).await
+    }
+
+    /// Executes the tasks of an installation with an already validated config
+    async fn install(&self, config: Config) -> AppResult<()> {
+        let mut resuming = self.resume;
+        let seed = {
+            let mut journal = self.journal.lock().await;
+            let path = journal.path().to_owned();
+            *journal = if self.resume {
+                Journal::load(path).await?
+            } else {
+                Journal::new(path)
+            };
+            journal.seed.clone()
+        };
+        let config = config.with_hashed_passwords(&seed);
+        *self.script_config.lock().await = Some(config.clone());
+        *self.fstab.lock().await = config.partitions.fstab(&seed)?;
+
+        let steps = task_steps(config, self.loader.load_custom_tasks()?, &seed)?;
+        let total = steps
+            .iter()
+            .filter(|s| matches!(s, TaskStep::Run(_)))
+            .count();
+        let mut completed = 0;
+        self.emit(TaskEvent::progress(completed, total));
+
+        for step in steps {
+            if self.cancelled.load(Ordering::SeqCst) {
+                return Err(AppError::Cancelled);
+            }
+            match step {
+                TaskStep::Run(task) => {
+                    if resuming {
+                        let files = task.files();
+                        let hash = config_hash(&task.args()?);
 
-        if let Some(unakite) = config.unakite {
-            self.configure_unakite(unakite).await?;
+                        match self.journal.lock().await.completed(files.name()) {
+                            Some(entry) if entry.config_hash == hash => {
+                                tracing::info!("Skipping completed task {}", files.name());
+                                self.emit(TaskEvent::TaskSkipped {
+                                    task: files.name().into(),
+                                    reason: "already completed".into(),
+                                });
+                                completed += 1;
+                                self.emit(TaskEvent::progress(completed, total));
+                                continue;
+                            }
+                            Some(_) => return Err(AppError::ConfigChanged(files.name().into())),
+                            None => resuming = false,
+                        }
+                    }
+                    for message in task.check_target(&utils::TARGET_PATH) {
+                        tracing::warn!("{message}");
+                        self.emit(TaskEvent::TaskWarning {
+                            task: task.files().name().into(),
+                            message,
+                        });
+                    }
+                    task.execute(self).await?;
+                    completed += 1;
+                    self.emit(TaskEvent::progress(completed, total));
+                }
+                TaskStep::Skip(files, reason) => {
+                    if resuming && self.journal.lock().await.completed(files.name()).is_some() {
+                        return Err(AppError::ConfigChanged(files.name().into()));
+                    }
+                    self.emit(TaskEvent::TaskSkipped {
+                        task: files.name().into(),
+                        reason: reason.to_string(),
+                    });
+                }
+            }
         }
 
         Ok(())
     }
 
+    /// Returns the tasks, scripts and hooks that would be executed
+    /// when installing from the given system configuration
+    /// without executing any of them
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn plan(&self) -> AppResult<InstallPlan> {
+        let config = self.config.clone().ok_or(AppError::MissingConfig)?;
+        // the IDs of a preview differ from the ones of the installation
+        let tasks = task_steps(
+            config.redacted(),
+            self.loader.load_custom_tasks()?,
+            &generate_seed(),
+        )?
+        .into_iter()
+        .map(|step| PlannedTask::new(step, &self.loader))
+        .collect::<serde_json::Result<_>>()?;
+
+        Ok(InstallPlan { tasks })
+    }
+
     async fn execute_task<S: Script>(&self, args: S::Args) -> AppResult<()> {
-        if let Some(pre_hook) = self.loader.load_hook::<S>(HookType::Pre) {
-            self.execute(pre_hook, args.clone()).await?;
-        }
-        let script = self.loader.load::<S>()?;
+        self.execute_task_files(TaskFiles::of::<S>(), args).await
+    }
 
-        if let Some(post_hook) = self.loader.load_hook::<S>(HookType::Post) {
-            self.execute(script, args.clone()).await?;
-            self.execute(post_hook, args).await?;
-        } else {
-            self.execute(script, args.clone()).await?;
+    /// Executes a user defined task. Its files are only known from the manifest
+    async fn execute_custom_task(&self, task: CustomTask) -> AppResult<()> {
+        self.execute_task_files(task.manifest.files(), task.args)
+            .await
+    }
+
+    async fn execute_task_files<A: TaskArgs>(&self, files: TaskFiles, args: A) -> AppResult<()> {
+        let task = files.name();
+        let hash = config_hash(&serde_json::to_value(&args)?);
+        {
+            let mut journal = self.journal.lock().await;
+            journal.start(task, hash);
+            journal.save().await?;
         }
+        self.emit(TaskEvent::TaskStarted { task: task.into() });
+        let result = self.execute_task_scripts(&files, args).await;
+        let status = match &result {
+            Ok(_) => {
+                self.emit(TaskEvent::TaskFinished { task: task.into() });
+                TaskStatus::Completed
+            }
+            Err(e) => {
+                self.emit(TaskEvent::TaskFailed {
+                    task: task.into(),
+                    error: e.to_string(),
+                });
+                TaskStatus::Failed
+            }
+        };
+        let mut journal = self.journal.lock().await;
+        journal.finish(task, status);
+        journal.save().await?;
 
-        Ok(())
+        result
+    }
+
+    async fn execute_task_scripts<A: TaskArgs>(&self, files: &TaskFiles, args: A) -> AppResult<()> {
+        self.execute_hook(files, HookType::Pre, args.clone())
+            .await?;
+        let script = self.loader.load(files.script())?;
+        self.execute(files, script, args.clone()).await?;
+        self.execute_hook(files, HookType::Post, args).await
+    }
+
+    async fn execute_hook<A: TaskArgs>(
+        &self,
+        files: &TaskFiles,
+        hook_type: HookType,
+        args: A,
+    ) -> AppResult<()> {
+        let (status, result) = match self.loader.load_hook(files.hook(hook_type)) {
+            Some(hook) => {
+                self.emit(TaskEvent::HookStarted {
+                    task: files.name().into(),
+                    hook: hook_type,
+                });
+                let result = self.execute(files, hook, args).await;
+                let status = if result.is_ok() {
+                    HookStatus::Completed
+                } else {
+                    HookStatus::Failed
+                };
+                (status, result)
+            }
+            None => (HookStatus::Missing, Ok(())),
+        };
+        let mut journal = self.journal.lock().await;
+        journal.hook(files.name(), hook_type, status);
+        journal.save().await?;
+
+        result
     }
 
     #[inline]
-    async fn execute<S: Script>(&self, mut script: NuScript<S>, args: S::Args) -> AppResult<()> {
-        if let Some(cfg) = self.config.as_ref() {
-            script.set_global_var("TRM_CONFIG", cfg.to_owned())
+    async fn execute<A: TaskArgs>(
+        &self,
+        files: &TaskFiles,
+        mut script: NuScript<A>,
+        args: A,
+    ) -> AppResult<()> {
+        let config = match self.script_config.lock().await.clone() {
+            Some(config) => config,
+            None => self.config.clone().unwrap_or_else(Config::empty),
+        };
+        let fstab = self.fstab.lock().await.clone();
+        // only tasks that explicitly request them get to see the passwords
+        let config = if files.secrets() {
+            config
         } else {
-            script.set_global_var("TRM_CONFIG", Config::empty())
-        }
-        .set_global_var("TRM_VERSION", env!("CARGO_PKG_VERSION"))
-        .execute(args)
-        .await
+            config.redacted()
+        };
+        script
+            .set_global_var("TRM_CONFIG", config)
+            .set_global_var("TRM_FSTAB", fstab)
+            .set_global_var("TRM_VERSION", env!("CARGO_PKG_VERSION"))
+            .execute(args, |line| {
+                self.emit(TaskEvent::ScriptOutput {
+                    task: files.name().into(),
+                    line,
+                })
+            })
+            .await
+    }
+
+    fn emit(&self, event: TaskEvent) {
+        // sending only fails if nobody is subscribed
+        let _ = self.events.send(event);
     }
 }
 
@@ -121,6 +335,112 @@ impl Default for TaskExecutor {
         Self {
             loader: ScriptLoader::new(),
             config: None,
+            journal: Mutex::new(Journal::new(utils::JOURNAL_PATH.to_owned())),
+            script_config: Mutex::new(None),
+            fstab: Mutex::new(None),
+            resume: false,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+
+    fn executor(dir: &Path, config: Config, resume: bool) -> TaskExecutor {
+        TaskExecutor {
+            config: Some(config),
+            loader: ScriptLoader::with_dir(dir),
+            journal: Mutex::new(Journal::new(journal_path(dir))),
+            ..Default::default()
         }
+        .resume(resume)
+    }
+
+    fn journal_path(dir: &Path) -> PathBuf {
+        dir.join("journal.json")
+    }
+
+    async fn journal(dir: &Path) -> Journal {
+        Journal::load(journal_path(dir)).await.unwrap()
+    }
+
+    /// Installs once with the config and marks the given task as failed in the journal
+    async fn interrupted_install(dir: &Path, failed: &str) {
+        generate_script_files(dir).await.unwrap();
+        // the generated hooks print to the terminal
+        std::fs::remove_dir_all(dir.join("hooks")).unwrap();
+        executor(dir, Config::empty(), false)
+            .install(Config::empty())
+            .await
+            .unwrap();
+        let mut journal = journal(dir).await;
+        journal.finish(failed, TaskStatus::Failed);
+        journal.save().await.unwrap();
+    }
+
+    fn started_tasks(events: &mut broadcast::Receiver<TaskEvent>) -> Vec<String> {
+        let mut started = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let TaskEvent::TaskStarted { task } = event {
+                started.push(task);
+            }
+        }
+        started
+    }
+
+    #[tokio::test]
+    async fn resuming_skips_completed_tasks() {
+        let dir = tempfile::tempdir().unwrap();
+        interrupted_install(dir.path(), "install-desktop").await;
+
+        let executor = executor(dir.path(), Config::empty(), true);
+        let mut events = executor.subscribe();
+        executor.install(Config::empty()).await.unwrap();
+
+        assert_eq!(
+            started_tasks(&mut events),
+            ["install-desktop", "install-extra-packages"]
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_to_resume_with_a_changed_config() {
+        let dir = tempfile::tempdir().unwrap();
+        interrupted_install(dir.path(), "install-desktop").await;
+        let mut config = Config::empty();
+        config.network.hostname = "changed".into();
+
+        let result = executor(dir.path(), config.clone(), true)
+            .install(config)
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(AppError::ConfigChanged(task)) if task == "configure-network"
+        ));
+    }
+
+    #[tokio::test]
+    async fn reuses_the_seed_on_resume() {
+        let dir = tempfile::tempdir().unwrap();
+        interrupted_install(dir.path(), "install-desktop").await;
+        let seed = journal(dir.path()).await.seed;
+
+        executor(dir.path(), Config::empty(), true)
+            .install(Config::empty())
+            .await
+            .unwrap();
+        assert_eq!(journal(dir.path()).await.seed, seed);
+
+        executor(dir.path(), Config::empty(), false)
+            .install(Config::empty())
+            .await
+            .unwrap();
+        assert_ne!(journal(dir.path()).await.seed, seed);
     }
 }
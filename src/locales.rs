@@ -0,0 +1,247 @@
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use crate::{error::AppResult, utils::LOCALE_ROOT_PATH};
+
+const SUPPORTED_LOCALES_PATH: &str = "usr/share/i18n/SUPPORTED";
+const KEYMAPS_PATH: &str = "usr/share/kbd/keymaps";
+const ZONEINFO_PATH: &str = "usr/share/zoneinfo";
+
+/// Directories of the zoneinfo database that duplicate the regular timezones
+const ZONEINFO_VARIANTS: &[&str] = &["posix", "right"];
+
+/// The magic number at the start of every compiled timezone file
+const TZIF_MAGIC: &[u8] = b"TZif";
+
+/// The minimum Jaro-Winkler similarity of a suggested value
+const SUGGESTION_THRESHOLD: f64 = 0.8;
+
+/// Lists the locales, keymaps and timezones available for the installed system.
+/// The installation medium ships the same data as the installed system
+/// so they are read from the running system by default
+#[derive(Clone, Debug)]
+pub struct LocaleData {
+    root: PathBuf,
+}
+
+impl Default for LocaleData {
+    fn default() -> Self {
+        Self::with_root(LOCALE_ROOT_PATH.as_path())
+    }
+}
+
+impl LocaleData {
+    /// Reads the data below the given root instead of `/`
+    pub fn with_root<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_owned(),
+        }
+    }
+
+    /// Returns the supported locales in the `locale.gen` format, e.g. `en_US.UTF-8 UTF-8`
+    pub fn locales(&self) -> AppResult<Vec<String>> {
+        let supported = fs::read_to_string(self.root.join(SUPPORTED_LOCALES_PATH))?;
+
+        Ok(supported
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(String::from)
+            .collect())
+    }
+
+    /// Returns the names of the console keymaps sorted by name
+    pub fn keymaps(&self) -> AppResult<Vec<String>> {
+        let mut keymaps = Vec::new();
+        visit_files(&self.root.join(KEYMAPS_PATH), &mut |path| {
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default();
+
+            if let Some(keymap) = name
+                .strip_suffix(".map.gz")
+                .or_else(|| name.strip_suffix(".map"))
+            {
+                keymaps.push(keymap.to_owned());
+            }
+        })?;
+        keymaps.sort();
+        keymaps.dedup();
+
+        Ok(keymaps)
+    }
+
+    /// Returns the names of the timezones in the zoneinfo database sorted by name
+    pub fn timezones(&self) -> AppResult<Vec<String>> {
+        let zoneinfo = self.root.join(ZONEINFO_PATH);
+        let mut timezones = Vec::new();
+
+        for entry in fs::read_dir(&zoneinfo)? {
+            let path = entry?.path();
+            let is_variant = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| ZONEINFO_VARIANTS.contains(&n));
+
+            if !is_variant {
+                visit_files(&path, &mut |file| {
+                    if is_timezone_file(file) {
+                        if let Ok(name) = file.strip_prefix(&zoneinfo) {
+                            timezones.push(name.to_string_lossy().into_owned());
+                        }
+                    }
+                })?;
+            }
+        }
+        timezones.sort();
+
+        Ok(timezones)
+    }
+}
+
+/// Returns the known value most similar to the given one to suggest it for typos
+pub fn closest_match<'a, S: AsRef<str>>(value: &str, known: &'a [S]) -> Option<&'a str> {
+    let value = value.to_lowercase();
+
+    known
+        .iter()
+        .map(|k| {
+            (
+                k.as_ref(),
+                strsim::jaro_winkler(&value, &k.as_ref().to_lowercase()),
+            )
+        })
+        .filter(|(_, similarity)| *similarity >= SUGGESTION_THRESHOLD)
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(k, _)| k)
+}
+
+/// Calls the function for every file in the given directory and its subdirectories
+fn visit_files(path: &Path, visit: &mut dyn FnMut(&Path)) -> AppResult<()> {
+    if fs::metadata(path)?.is_dir() {
+        for entry in fs::read_dir(path)? {
+            visit_files(&entry?.path(), visit)?;
+        }
+    } else {
+        visit(path);
+    }
+
+    Ok(())
+}
+
+fn is_timezone_file(path: &Path) -> bool {
+    let mut magic = [0; 4];
+
+    File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .is_ok()
+        && magic == TZIF_MAGIC
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TZIF: &[u8] = b"TZif2\0\0\0";
+
+    fn write(root: &Path, path: &str, content: &[u8]) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn fake_root() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        let root_path = root.path();
+        write(
+            root_path,
+            SUPPORTED_LOCALES_PATH,
+            b"# generated by glibc\n\nen_US.UTF-8 UTF-8\n  de_DE.UTF-8 UTF-8  \nde_DE ISO-8859-1\n",
+        );
+        write(
+            root_path,
+            "usr/share/kbd/keymaps/i386/qwertz/de.map.gz",
+            b"",
+        );
+        write(
+            root_path,
+            "usr/share/kbd/keymaps/i386/qwerty/us.map.gz",
+            b"",
+        );
+        write(root_path, "usr/share/kbd/keymaps/i386/qwerty/us.map", b"");
+        write(root_path, "usr/share/kbd/keymaps/include/compose.inc", b"");
+        write(root_path, "usr/share/zoneinfo/UTC", TZIF);
+        write(root_path, "usr/share/zoneinfo/Europe/Berlin", TZIF);
+        write(
+            root_path,
+            "usr/share/zoneinfo/America/Argentina/Salta",
+            TZIF,
+        );
+        write(root_path, "usr/share/zoneinfo/posix/Europe/Berlin", TZIF);
+        write(root_path, "usr/share/zoneinfo/right/UTC", TZIF);
+        write(
+            root_path,
+            "usr/share/zoneinfo/zone.tab",
+            b"DE\t+5230+01322\tEurope/Berlin\n",
+        );
+
+        root
+    }
+
+    #[test]
+    fn lists_supported_locales() {
+        let root = fake_root();
+        let locales = LocaleData::with_root(root.path()).locales().unwrap();
+
+        assert_eq!(
+            locales,
+            ["en_US.UTF-8 UTF-8", "de_DE.UTF-8 UTF-8", "de_DE ISO-8859-1"]
+        );
+    }
+
+    #[test]
+    fn lists_keymaps() {
+        let root = fake_root();
+        let keymaps = LocaleData::with_root(root.path()).keymaps().unwrap();
+
+        assert_eq!(keymaps, ["de", "us"]);
+    }
+
+    #[test]
+    fn lists_timezones_without_variants() {
+        let root = fake_root();
+        let timezones = LocaleData::with_root(root.path()).timezones().unwrap();
+
+        assert_eq!(
+            timezones,
+            ["America/Argentina/Salta", "Europe/Berlin", "UTC"]
+        );
+    }
+
+    #[test]
+    fn fails_without_locale_data() {
+        let root = tempfile::tempdir().unwrap();
+
+        assert!(LocaleData::with_root(root.path()).locales().is_err());
+    }
+
+    #[test]
+    fn suggests_similar_values() {
+        let timezones = ["Europe/Berlin", "Europe/Brussels", "America/New_York"];
+
+        assert_eq!(
+            closest_match("europe/berln", &timezones),
+            Some("Europe/Berlin")
+        );
+        assert_eq!(
+            closest_match("America/NewYork", &timezones),
+            Some("America/New_York")
+        );
+        assert_eq!(closest_match("Asia/Tokyo", &timezones), None);
+        assert_eq!(closest_match("de", &[] as &[&str]), None);
+    }
+}
@@ -1,7 +1,18 @@
-use args::{Args, Command, GenerateScriptsArgs, InstallFromConfigArgs};
+use args::{
+    Args, Command, ConfigArgs, ConfigCommand, DaemonArgs, DevicesArgs, FstabArgs,
+    GenerateScriptsArgs, InstallFromConfigArgs, ListArgs, ListKind, PlanArgs, RenderArgs,
+    ValidateArgs,
+};
 use clap::Parser;
-use tokio::{fs::OpenOptions, io::AsyncReadExt};
-use tourmaline::{config::Config, error::AppResult, generate_script_files, TaskExecutor};
+use tourmaline::{
+    config::Config,
+    devices::DeviceScanner,
+    error::{AppError, AppResult},
+    generate_script_files,
+    journal::generate_seed,
+    locales::LocaleData,
+    TaskExecutor,
+};
 
 mod args;
 
@@ -13,22 +24,165 @@ async fn main() {
 
     match args.command {
         Command::InstallFromConfig(args) => install_from_config(args).await,
+        Command::Plan(args) => plan(args).await,
+        Command::Validate(args) => validate(args).await,
+        Command::Fstab(args) => fstab(args),
+        Command::Config(ConfigCommand::Render(args)) => render_config(args),
+        Command::Schema => schema(),
+        Command::Devices(args) => devices(args),
+        Command::List(args) => list(args),
+        Command::Daemon(args) => daemon(args).await,
         Command::GenerateScripts(args) => generate_scripts(args).await,
     }
     .unwrap();
 }
 
 async fn install_from_config(args: InstallFromConfigArgs) -> AppResult<()> {
-    let mut file = OpenOptions::new().read(true).open(args.path).await?;
-    let mut cfg_contents = String::new();
-    file.read_to_string(&mut cfg_contents).await?;
-    let config: Config = serde_json::from_str(&cfg_contents)?;
+    let config = read_config(args.config)?;
 
     TaskExecutor::with_config(config)
+        .resume(args.resume)
         .install_from_config()
         .await
 }
 
+async fn plan(args: PlanArgs) -> AppResult<()> {
+    let config = read_config(args.config)?;
+    let plan = TaskExecutor::with_config(config).plan()?;
+    print!("{plan}");
+
+    Ok(())
+}
+
+async fn validate(args: ValidateArgs) -> AppResult<()> {
+    let errors = match read_config(args.config) {
+        Ok(config) => config.validate(),
+        Err(AppError::InvalidConfig(errors)) => errors,
+        Err(e) => return Err(e),
+    };
+
+    if errors.is_empty() {
+        println!("The config is valid");
+    } else {
+        errors.iter().for_each(|e| eprintln!("{e}"));
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+fn fstab(args: FstabArgs) -> AppResult<()> {
+    let config = read_config(args.config)?;
+
+    match config.partitions.fstab(&generate_seed())? {
+        Some(fstab) => print!("{fstab}"),
+        None => eprintln!("The fstab can't be generated for automatic partitioning"),
+    }
+
+    Ok(())
+}
+
+fn render_config(args: RenderArgs) -> AppResult<()> {
+    let mut config = read_config(args.config)?;
+
+    if !args.show_secrets {
+        config = config.redacted();
+    }
+    println!("{}", config.render(args.output)?);
+
+    Ok(())
+}
+
+fn schema() -> AppResult<()> {
+    let schema = serde_json::to_string_pretty(&Config::json_schema())?;
+    println!("{schema}");
+
+    Ok(())
+}
+
+fn devices(args: DevicesArgs) -> AppResult<()> {
+    let devices = DeviceScanner::default().scan()?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&devices)?);
+        return Ok(());
+    }
+    for device in devices {
+        let mut flags = Vec::new();
+        flags.extend(device.partition_table);
+        flags.extend(device.filesystem);
+        flags.push(if device.rotational { "hdd" } else { "ssd" }.into());
+        if device.removable {
+            flags.push("removable".into());
+        }
+        if device.read_only {
+            flags.push("read-only".into());
+        }
+        println!(
+            "{:<16} {:>10}  {}  ({})",
+            device.path.display(),
+            human_size(device.size),
+            device.model.as_deref().unwrap_or("unknown model"),
+            flags.join(", ")
+        );
+
+        for partition in device.partitions {
+            println!(
+                "  {:<14} {:>10}  {}{}",
+                partition.path.display(),
+                human_size(partition.size),
+                partition.filesystem.as_deref().unwrap_or("-"),
+                partition
+                    .label
+                    .map(|l| format!(" \"{l}\""))
+                    .unwrap_or_default()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the locales, keymaps or timezones available for the installed system
+fn list(args: ListArgs) -> AppResult<()> {
+    let data = LocaleData::default();
+    let values = match args.kind {
+        ListKind::Locales => data.locales()?,
+        ListKind::Keymaps => data.keymaps()?,
+        ListKind::Timezones => data.timezones()?,
+    };
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&values)?);
+    } else {
+        values.iter().for_each(|v| println!("{v}"));
+    }
+
+    Ok(())
+}
+
+/// Formats the size rounded to one decimal of the largest fitting binary unit
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{size:.1}{}", UNITS[unit])
+}
+
+async fn daemon(args: DaemonArgs) -> AppResult<()> {
+    tourmaline::daemon::serve(&args.socket, args.group.as_deref()).await
+}
+
+fn read_config(args: ConfigArgs) -> AppResult<Config> {
+    Config::load(&args.path, args.format, &args.overrides)
+}
+
 async fn generate_scripts(args: GenerateScriptsArgs) -> AppResult<()> {
     generate_script_files(args.path).await
 }
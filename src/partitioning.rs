@@ -0,0 +1,970 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use embed_nu::rusty_value::*;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    devices::DeviceScanner,
+    encryption::{EncryptedDevice, EncryptionConfig},
+    error::{AppError, AppResult},
+    tasks::{
+        json_into_rusty_value, FileSystem, PartitionSpec, PartitionTable, RaidArray, RaidLevel,
+        VolumeConfig, VolumeGroup,
+    },
+};
+
+/// Partitions are aligned to 1 MiB
+pub const ALIGNMENT: u64 = 1 << 20;
+
+/// The space at the end of the device reserved for the backup GPT header
+const GPT_BACKUP_SIZE: u64 = 33 * 512;
+
+pub const ESP_GUID: &str = "C12A7328-F81F-11D2-BA4B-00A0C93EC93B";
+pub const BIOS_BOOT_GUID: &str = "21686148-6449-6E6F-744E-656564454649";
+pub const SWAP_GUID: &str = "0657FD6D-A4AB-43C4-84E5-0933C84B4F4F";
+pub const LINUX_ROOT_X86_64_GUID: &str = "4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709";
+pub const LINUX_HOME_GUID: &str = "933AC7E1-2EB4-4F13-B844-0E14E2AEF915";
+pub const LINUX_FILESYSTEM_GUID: &str = "0FC63DAF-8483-4772-8E79-3D69D8477DE4";
+
+const MBR_ESP_TYPE: &str = "ef";
+const MBR_SWAP_TYPE: &str = "82";
+const MBR_LINUX_TYPE: &str = "83";
+
+/// The size of a partition in a layout.
+/// Written as bytes with an optional unit (`512MiB`, `20G`, `1TB`),
+/// a percentage of the usable space (`25%`) or `rest` for the remaining space
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum PartitionSize {
+    Bytes(u64),
+    Percent(f64),
+    Rest,
+}
+
+impl FromStr for PartitionSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if s.eq_ignore_ascii_case("rest") {
+            return Ok(Self::Rest);
+        }
+        if let Some(percent) = s.strip_suffix('%') {
+            let percent: f64 = percent
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid percentage {s}"))?;
+
+            return if percent > 0.0 && percent <= 100.0 {
+                Ok(Self::Percent(percent))
+            } else {
+                Err(format!("The percentage {s} must be between 0% and 100%"))
+            };
+        }
+        let unit_start = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(unit_start);
+        let number: f64 = number
+            .parse()
+            .map_err(|_| format!("Invalid partition size {s}"))?;
+        let factor: u64 = match unit.trim() {
+            "" | "B" => 1,
+            "K" | "KiB" => 1 << 10,
+            "M" | "MiB" => 1 << 20,
+            "G" | "GiB" => 1 << 30,
+            "T" | "TiB" => 1 << 40,
+            "KB" => 1_000,
+            "MB" => 1_000_000,
+            "GB" => 1_000_000_000,
+            "TB" => 1_000_000_000_000,
+            unit => return Err(format!("Unknown size unit {unit}")),
+        };
+
+        Ok(Self::Bytes((number * factor as f64) as u64))
+    }
+}
+
+impl TryFrom<String> for PartitionSize {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PartitionSize> for String {
+    fn from(size: PartitionSize) -> Self {
+        size.to_string()
+    }
+}
+
+impl fmt::Display for PartitionSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionSize::Bytes(bytes) => format_size(*bytes).fmt(f),
+            PartitionSize::Percent(percent) => write!(f, "{percent}%"),
+            PartitionSize::Rest => "rest".fmt(f),
+        }
+    }
+}
+
+impl RustyValue for PartitionSize {
+    fn into_rusty_value(self) -> Value {
+        Value::Primitive(Primitive::String(self.to_string()))
+    }
+}
+
+impl JsonSchema for PartitionSize {
+    fn schema_name() -> String {
+        "PartitionSize".into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+/// The fully resolved steps to set up the storage in the order they are executed in
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue)]
+pub struct PartitionPlan {
+    pub steps: Vec<PlanStep>,
+}
+
+/// A single action of the partition plan
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PlanStep {
+    /// Creates a new partition table with the given partitions
+    PartitionDisk {
+        device: PathBuf,
+        table: PartitionTable,
+        /// The GPT disk GUID or the MBR disk signature
+        disk_id: String,
+        partitions: Vec<PlannedPartition>,
+    },
+    /// Creates a software RAID array with mdadm
+    CreateRaid {
+        name: String,
+        /// The device of the array, e.g. `/dev/md/system`
+        device: PathBuf,
+        level: RaidLevel,
+        devices: Vec<PathBuf>,
+    },
+    /// Creates the physical volumes and the LVM volume group on them
+    CreateVolumeGroup {
+        name: String,
+        physical_volumes: Vec<PathBuf>,
+    },
+    /// Creates a logical volume. Either the size or the extents are set
+    CreateLogicalVolume {
+        name: String,
+        volume_group: String,
+        device: PathBuf,
+        /// The size in bytes, passed as `lvcreate -L <size>B`
+        size: Option<u64>,
+        /// The size in extents passed to `lvcreate -l`, e.g. `25%VG` or `100%FREE`
+        extents: Option<String>,
+    },
+    /// Formats the device with LUKS2 and unlocks it to the mapper device
+    Encrypt {
+        device: PathBuf,
+        name: String,
+        mapper: PathBuf,
+        /// The UUID of the LUKS header
+        uuid: String,
+        encryption: EncryptionConfig,
+    },
+    Format {
+        device: PathBuf,
+        filesystem: FileSystem,
+        label: Option<String>,
+        /// The UUID the filesystem is created with. Unset for filesystems
+        /// that don't support choosing one
+        uuid: Option<String>,
+    },
+    /// Creates the subvolumes on the formatted btrfs filesystem in order
+    CreateSubvolumes {
+        device: PathBuf,
+        subvolumes: Vec<String>,
+    },
+    Mount {
+        device: PathBuf,
+        mountpoint: PathBuf,
+        /// The btrfs subvolume to mount. Passed as `subvol=` mount option
+        subvolume: Option<String>,
+        options: Vec<String>,
+    },
+    EnableSwap {
+        device: PathBuf,
+    },
+}
+
+/// A partition with its final position on the device. Offsets are in bytes
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue)]
+pub struct PlannedPartition {
+    pub number: u32,
+    /// The device of the partition, e.g. `/dev/sda1` or `/dev/nvme0n1p1`
+    pub device: PathBuf,
+    pub start: u64,
+    pub size: u64,
+    /// The GPT type GUID or MBR type id of the partition
+    pub partition_type: String,
+    /// The GPT partition name
+    pub name: Option<String>,
+    /// The PARTUUID. The unique partition GUID on GPT or `<disk signature>-<number>` on MBR
+    pub uuid: String,
+}
+
+/// The device the root filesystem is mounted from
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue)]
+pub struct RootDevice {
+    /// The device holding the root filesystem
+    pub device: PathBuf,
+    /// The UUID of the root filesystem if it is created by the plan
+    pub uuid: Option<String>,
+    /// The btrfs subvolume mounted as the root filesystem
+    pub subvolume: Option<String>,
+    /// The encrypted container if the root filesystem is encrypted
+    pub encryption: Option<EncryptedDevice>,
+    /// The storage layers below the root filesystem starting at the disk
+    pub layers: Vec<StorageLayer>,
+}
+
+/// The swap device the system resumes from after hibernation
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue)]
+pub struct ResumeDevice {
+    pub device: PathBuf,
+    /// The UUID of the swap space if it is created by the plan
+    pub uuid: Option<String>,
+}
+
+/// A layer that needs to be assembled in the initramfs before the root filesystem can be mounted
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, RustyValue)]
+pub enum StorageLayer {
+    Raid,
+    Lvm,
+    Encryption,
+}
+
+impl RustyValue for PlanStep {
+    fn into_rusty_value(self) -> Value {
+        let value = serde_json::to_value(self).expect("plan steps are always serializable");
+        json_into_rusty_value(value)
+    }
+}
+
+impl PartitionPlan {
+    /// Resolves the device the root filesystem is mounted from
+    /// by following the plan back from the mount of `/`
+    pub fn root_device(&self) -> Option<RootDevice> {
+        let (device, subvolume) = self.steps.iter().find_map(|step| match step {
+            PlanStep::Mount {
+                device,
+                mountpoint,
+                subvolume,
+                ..
+            } if mountpoint == Path::new("/") => Some((device.to_owned(), subvolume.to_owned())),
+            _ => None,
+        })?;
+        let mut root = RootDevice {
+            uuid: self.filesystem_uuid(&device),
+            device: device.clone(),
+            subvolume,
+            encryption: None,
+            layers: Vec::new(),
+        };
+        let mut devices = vec![device];
+
+        while let Some(device) = devices.pop() {
+            for step in &self.steps {
+                match step {
+                    PlanStep::Encrypt {
+                        device: encrypted,
+                        name,
+                        mapper,
+                        uuid,
+                        encryption,
+                    } if mapper == &device => {
+                        root.layers.push(StorageLayer::Encryption);
+                        // the outermost encryption is the one the kernel parameters refer to
+                        if root.encryption.is_none() {
+                            root.encryption = Some(EncryptedDevice {
+                                device: encrypted.to_owned(),
+                                name: name.to_owned(),
+                                mapper: mapper.to_owned(),
+                                uuid: Some(uuid.to_owned()),
+                                tpm2: encryption.tpm2,
+                            });
+                        }
+                        devices.push(encrypted.to_owned());
+                    }
+                    PlanStep::CreateRaid {
+                        device: array,
+                        devices: members,
+                        ..
+                    } if array == &device => {
+                        root.layers.push(StorageLayer::Raid);
+                        devices.extend(members.iter().cloned());
+                    }
+                    PlanStep::CreateLogicalVolume {
+                        device: volume,
+                        volume_group,
+                        ..
+                    } if volume == &device => {
+                        root.layers.push(StorageLayer::Lvm);
+                        devices.extend(self.physical_volumes(volume_group));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        // the layers were collected from the root filesystem down to the disk
+        root.layers.reverse();
+        let mut seen = Vec::new();
+        root.layers.retain(|l| {
+            let new = !seen.contains(l);
+            seen.push(*l);
+            new
+        });
+
+        Some(root)
+    }
+
+    /// Returns the first swap device that can be used for hibernation.
+    /// Swap encrypted on its own can't be unlocked together with the root filesystem
+    pub fn resume_device(&self) -> Option<ResumeDevice> {
+        self.steps
+            .iter()
+            .filter_map(|step| match step {
+                PlanStep::EnableSwap { device } => Some(device),
+                _ => None,
+            })
+            .find(|device| {
+                !self.steps.iter().any(
+                    |step| matches!(step, PlanStep::Encrypt { mapper, .. } if &mapper == device),
+                )
+            })
+            .map(|device| ResumeDevice {
+                device: device.to_owned(),
+                uuid: self.filesystem_uuid(device),
+            })
+    }
+
+    fn filesystem_uuid(&self, device: &Path) -> Option<String> {
+        self.steps.iter().find_map(|step| match step {
+            PlanStep::Format {
+                device: formatted,
+                uuid,
+                ..
+            } if formatted == device => uuid.clone(),
+            _ => None,
+        })
+    }
+
+    fn physical_volumes(&self, volume_group: &str) -> Vec<PathBuf> {
+        self.steps
+            .iter()
+            .filter_map(|step| match step {
+                PlanStep::CreateVolumeGroup {
+                    name,
+                    physical_volumes,
+                } if name == volume_group => Some(physical_volumes.clone()),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+}
+
+/// Collects the steps of a [PartitionPlan]. Devices are encrypted and formatted
+/// right after they are created while mounts are deferred to the end
+pub struct PlanBuilder {
+    seed: String,
+    steps: Vec<PlanStep>,
+    mounts: Vec<PlanStep>,
+}
+
+impl PlanBuilder {
+    /// Creates a builder deriving the IDs of partitions and filesystems from the given seed
+    pub fn new<S: ToString>(seed: S) -> Self {
+        Self {
+            seed: seed.to_string(),
+            steps: Vec::new(),
+            mounts: Vec::new(),
+        }
+    }
+
+    /// Partitions the disk with the given layout. Symlinks to the disk like `/dev/disk/by-id` are resolved
+    pub fn disk(
+        &mut self,
+        device: &Path,
+        table: PartitionTable,
+        layout: &[PartitionSpec],
+    ) -> AppResult<()> {
+        let size = DeviceScanner::default()
+            .find(device)?
+            .map(|d| d.size)
+            .filter(|size| *size > 0)
+            .ok_or_else(|| AppError::DeviceNotFound(device.to_owned()))?;
+        let partitions = layout_partitions(&self.seed, device, table, layout, size)
+            .map_err(AppError::InvalidPartitionLayout)?;
+        let devices = partitions
+            .iter()
+            .map(|p| p.device.clone())
+            .collect::<Vec<_>>();
+
+        self.steps.push(PlanStep::PartitionDisk {
+            device: device.to_owned(),
+            table,
+            disk_id: disk_id(&self.seed, device, table),
+            partitions,
+        });
+        for (device, spec) in devices.iter().zip(layout) {
+            self.volume(device, &spec.volume);
+        }
+
+        Ok(())
+    }
+
+    pub fn raid(&mut self, array: &RaidArray) {
+        let device = raid_device(&array.name);
+
+        self.steps.push(PlanStep::CreateRaid {
+            name: array.name.clone(),
+            device: device.clone(),
+            level: array.level,
+            devices: array.devices.clone(),
+        });
+        self.volume(&device, &array.volume);
+    }
+
+    pub fn volume_group(&mut self, group: &VolumeGroup) {
+        self.steps.push(PlanStep::CreateVolumeGroup {
+            name: group.name.clone(),
+            physical_volumes: group.physical_volumes.clone(),
+        });
+        for volume in &group.logical_volumes {
+            let device = lv_device(&group.name, &volume.name);
+            let (size, extents) = match volume.size {
+                PartitionSize::Bytes(bytes) => (Some(bytes), None),
+                PartitionSize::Percent(percent) => (None, Some(format!("{percent}%VG"))),
+                PartitionSize::Rest => (None, Some("100%FREE".into())),
+            };
+
+            self.steps.push(PlanStep::CreateLogicalVolume {
+                name: volume.name.clone(),
+                volume_group: group.name.clone(),
+                device: device.clone(),
+                size,
+                extents,
+            });
+            self.volume(&device, &volume.volume);
+        }
+    }
+
+    /// Adds the steps to encrypt, format and mount the given device
+    pub fn volume(&mut self, device: &Path, volume: &VolumeConfig) {
+        let mut device = device.to_owned();
+
+        if let Some(encryption) = &volume.encryption {
+            let encrypted = encryption.device(&device, volume.is_mounted_at("/"));
+
+            self.steps.push(PlanStep::Encrypt {
+                uuid: derive_uuid(&self.seed, "luks", &encrypted.device),
+                device: encrypted.device,
+                name: encrypted.name,
+                mapper: encrypted.mapper.clone(),
+                encryption: encryption.clone(),
+            });
+            device = encrypted.mapper;
+        }
+        if let Some(filesystem) = &volume.filesystem {
+            self.steps.push(PlanStep::Format {
+                device: device.clone(),
+                filesystem: filesystem.clone(),
+                label: volume.label.clone(),
+                uuid: filesystem_uuid(&self.seed, &device, filesystem),
+            });
+        }
+        let btrfs = volume.btrfs.clone().unwrap_or_default();
+
+        if !btrfs.subvolumes.is_empty() {
+            self.steps.push(PlanStep::CreateSubvolumes {
+                device: device.clone(),
+                subvolumes: btrfs.subvolumes.iter().map(|s| s.name.clone()).collect(),
+            });
+        }
+        if volume.is_swap() {
+            self.mounts.push(PlanStep::EnableSwap { device });
+            return;
+        }
+        if let Some(mountpoint) = &volume.mountpoint {
+            self.mounts.push(PlanStep::Mount {
+                device: device.clone(),
+                mountpoint: mountpoint.clone(),
+                subvolume: None,
+                options: btrfs.mount_options(&volume.mount_options),
+            });
+        }
+        for subvolume in &btrfs.subvolumes {
+            if let Some(mountpoint) = &subvolume.mountpoint {
+                self.mounts.push(PlanStep::Mount {
+                    device: device.clone(),
+                    mountpoint: mountpoint.clone(),
+                    subvolume: Some(subvolume.name.clone()),
+                    options: btrfs.mount_options(&subvolume.mount_options),
+                });
+            }
+        }
+    }
+
+    pub fn build(mut self) -> PartitionPlan {
+        // parents need to be mounted before the filesystems mounted inside them
+        // and swap is enabled after everything is mounted
+        self.mounts.sort_by_key(|step| match step {
+            PlanStep::Mount { mountpoint, .. } => mountpoint.components().count(),
+            _ => usize::MAX,
+        });
+        self.steps.append(&mut self.mounts);
+
+        PartitionPlan { steps: self.steps }
+    }
+}
+
+/// Computes the position of all partitions in the layout on a device with the given size.
+/// Percentages are relative to the usable space of the device
+pub fn layout_partitions(
+    seed: &str,
+    device: &Path,
+    table: PartitionTable,
+    layout: &[PartitionSpec],
+    device_size: u64,
+) -> Result<Vec<PlannedPartition>, String> {
+    let end = match table {
+        PartitionTable::Gpt => align_down(device_size.saturating_sub(GPT_BACKUP_SIZE)),
+        PartitionTable::Mbr => align_down(device_size),
+    };
+    let usable = end.saturating_sub(ALIGNMENT);
+    let sizes = layout
+        .iter()
+        .map(|spec| match spec.size {
+            PartitionSize::Bytes(bytes) => Some(align_up(bytes)),
+            PartitionSize::Percent(percent) => {
+                Some(align_down((usable as f64 * percent / 100.0) as u64))
+            }
+            PartitionSize::Rest => None,
+        })
+        .collect::<Vec<_>>();
+    let required: u64 = sizes.iter().flatten().sum();
+
+    if required > usable {
+        return Err(format!(
+            "the partitions need {} but the device {} only has {} of usable space",
+            format_size(required),
+            device.display(),
+            format_size(usable)
+        ));
+    }
+    let rest = usable - required;
+
+    if sizes.iter().any(Option::is_none) && rest < ALIGNMENT {
+        return Err(format!(
+            "no space is left on the device {} for the partition using the rest",
+            device.display()
+        ));
+    }
+    let mut start = ALIGNMENT;
+    let mut partitions = Vec::with_capacity(layout.len());
+    let disk_id = disk_id(seed, device, table);
+
+    for (i, (spec, size)) in layout.iter().zip(sizes).enumerate() {
+        let number = i as u32 + 1;
+        let size = size.unwrap_or(rest);
+        let partition = partition_device(device, number);
+
+        partitions.push(PlannedPartition {
+            number,
+            uuid: match table {
+                PartitionTable::Gpt => derive_uuid(seed, "partition", &partition),
+                PartitionTable::Mbr => format!("{disk_id}-{number:02x}"),
+            },
+            device: partition,
+            start,
+            size,
+            partition_type: spec.partition_type(table),
+            name: match table {
+                PartitionTable::Gpt => spec.volume.label.clone(),
+                PartitionTable::Mbr => None,
+            },
+        });
+        start += size;
+    }
+
+    Ok(partitions)
+}
+
+impl PartitionSpec {
+    /// Returns if the partition is an EFI system partition
+    pub fn is_esp(&self) -> bool {
+        match &self.type_guid {
+            Some(guid) => guid.eq_ignore_ascii_case(ESP_GUID),
+            None => self.volume.is_esp_mount(),
+        }
+    }
+
+    /// Returns if the partition is a BIOS boot partition used by GRUB on GPT
+    pub fn is_bios_boot(&self) -> bool {
+        self.type_guid
+            .as_ref()
+            .map(|guid| guid.eq_ignore_ascii_case(BIOS_BOOT_GUID))
+            .unwrap_or(false)
+    }
+
+    /// Returns the explicit GPT type GUID or derives the type from the usage of the partition
+    pub fn partition_type(&self, table: PartitionTable) -> String {
+        match table {
+            PartitionTable::Gpt => match &self.type_guid {
+                Some(guid) => guid.to_uppercase(),
+                None if self.is_esp() => ESP_GUID.into(),
+                None if self.volume.is_swap() => SWAP_GUID.into(),
+                None if self.volume.is_mounted_at("/") => LINUX_ROOT_X86_64_GUID.into(),
+                None if self.volume.is_mounted_at("/home") => LINUX_HOME_GUID.into(),
+                None => LINUX_FILESYSTEM_GUID.into(),
+            },
+            PartitionTable::Mbr if self.is_esp() => MBR_ESP_TYPE.into(),
+            PartitionTable::Mbr if self.volume.is_swap() => MBR_SWAP_TYPE.into(),
+            PartitionTable::Mbr => MBR_LINUX_TYPE.into(),
+        }
+    }
+}
+
+/// Returns if the given string is a GUID in the `8-4-4-4-12` hex digit format
+pub fn is_valid_guid(guid: &str) -> bool {
+    let groups = guid.split('-').collect::<Vec<_>>();
+
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(group, len)| group.len() == len && group.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Derives a random looking UUID from the seed of the installation, the purpose and the device.
+/// The seed is random per installation and kept in the journal
+/// so that resumed installations refer to the same filesystems
+fn derive_uuid(seed: &str, purpose: &str, device: &Path) -> String {
+    let mut bytes = Sha256::digest(format!("{seed}:{purpose}:{}", device.display()));
+    // mark the UUID as version 4 with the RFC 4122 variant
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = bytes[..16]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>();
+
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Returns the GPT disk GUID or the 32 bit MBR disk signature of the disk
+fn disk_id(seed: &str, device: &Path, table: PartitionTable) -> String {
+    let uuid = derive_uuid(seed, "disk", device);
+
+    match table {
+        PartitionTable::Gpt => uuid,
+        PartitionTable::Mbr => uuid[..8].to_owned(),
+    }
+}
+
+/// Returns the UUID the filesystem is created with in the format its mkfs tool expects
+fn filesystem_uuid(seed: &str, device: &Path, filesystem: &FileSystem) -> Option<String> {
+    let uuid = derive_uuid(seed, "filesystem", device);
+
+    match filesystem {
+        // FAT only has a 32 bit volume id written as XXXX-XXXX
+        FileSystem::VFAT | FileSystem::FAT | FileSystem::MSDOS => Some(format!(
+            "{}-{}",
+            uuid[..4].to_uppercase(),
+            uuid[4..8].to_uppercase()
+        )),
+        FileSystem::Ext2
+        | FileSystem::Ext3
+        | FileSystem::Ext4
+        | FileSystem::XFS
+        | FileSystem::BTRFS
+        | FileSystem::F2FS
+        | FileSystem::Swap => Some(uuid),
+        FileSystem::BFS | FileSystem::CramFS | FileSystem::Minix => None,
+    }
+}
+
+/// Returns the device of the RAID array with the given name
+pub fn raid_device(name: &str) -> PathBuf {
+    Path::new("/dev/md").join(name)
+}
+
+/// Returns the device of the logical volume in the given volume group
+pub fn lv_device(volume_group: &str, name: &str) -> PathBuf {
+    Path::new("/dev").join(volume_group).join(name)
+}
+
+/// Returns the device of the partition with the given number.
+/// Devices ending with a digit like `/dev/nvme0n1` use a `p` separator
+pub fn partition_device(device: &Path, number: u32) -> PathBuf {
+    let device = device.to_string_lossy();
+
+    if device.ends_with(|c: char| c.is_ascii_digit()) {
+        PathBuf::from(format!("{device}p{number}"))
+    } else {
+        PathBuf::from(format!("{device}{number}"))
+    }
+}
+
+fn align_up(bytes: u64) -> u64 {
+    bytes.div_ceil(ALIGNMENT) * ALIGNMENT
+}
+
+fn align_down(bytes: u64) -> u64 {
+    bytes / ALIGNMENT * ALIGNMENT
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [(&str, u64); 4] = [
+        ("TiB", 1 << 40),
+        ("GiB", 1 << 30),
+        ("MiB", 1 << 20),
+        ("KiB", 1 << 10),
+    ];
+
+    UNITS
+        .iter()
+        .find(|(_, factor)| bytes >= *factor && bytes.is_multiple_of(*factor))
+        .map(|(unit, factor)| format!("{}{unit}", bytes / factor))
+        .unwrap_or_else(|| format!("{bytes}B"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const GIB: u64 = 1 << 30;
+
+    fn layout(specs: serde_json::Value) -> Vec<PartitionSpec> {
+        serde_json::from_value(specs).unwrap()
+    }
+
+    fn desktop_layout() -> Vec<PartitionSpec> {
+        layout(json!([
+            { "size": "512MiB", "filesystem": "VFAT", "mountpoint": "/boot/efi", "label": "EFI" },
+            { "size": "10%", "filesystem": "Swap" },
+            { "size": "rest", "filesystem": "Ext4", "mountpoint": "/" },
+        ]))
+    }
+
+    #[test]
+    fn parses_partition_sizes() {
+        assert_eq!("512MiB".parse(), Ok(PartitionSize::Bytes(512 << 20)));
+        assert_eq!("1.5G".parse(), Ok(PartitionSize::Bytes(3 << 29)));
+        assert_eq!("20GB".parse(), Ok(PartitionSize::Bytes(20_000_000_000)));
+        assert_eq!(" 25% ".parse(), Ok(PartitionSize::Percent(25.0)));
+        assert_eq!("Rest".parse(), Ok(PartitionSize::Rest));
+        assert!("0%".parse::<PartitionSize>().is_err());
+        assert!("101%".parse::<PartitionSize>().is_err());
+        assert!("10 parsecs".parse::<PartitionSize>().is_err());
+    }
+
+    #[test]
+    fn lays_out_gpt_partitions() {
+        let device = Path::new("/dev/nvme0n1");
+        let partitions = layout_partitions(
+            "seed",
+            device,
+            PartitionTable::Gpt,
+            &desktop_layout(),
+            100 * GIB,
+        )
+        .unwrap();
+        let usable = 100 * GIB - 2 * ALIGNMENT;
+
+        let devices = partitions
+            .iter()
+            .map(|p| p.device.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            devices,
+            ["/dev/nvme0n1p1", "/dev/nvme0n1p2", "/dev/nvme0n1p3"]
+        );
+
+        let types = partitions
+            .iter()
+            .map(|p| p.partition_type.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(types, [ESP_GUID, SWAP_GUID, LINUX_ROOT_X86_64_GUID]);
+        assert_eq!(partitions[0].name.as_deref(), Some("EFI"));
+
+        assert_eq!(partitions[0].start, ALIGNMENT);
+        assert_eq!(partitions[0].size, 512 << 20);
+        assert_eq!(partitions[1].size, align_down(usable / 10));
+        // the partitions are contiguous and the last one fills the usable space
+        for pair in partitions.windows(2) {
+            assert_eq!(pair[0].start + pair[0].size, pair[1].start);
+        }
+        let last = &partitions[2];
+        assert_eq!(last.start + last.size, ALIGNMENT + usable);
+        assert!(partitions.iter().all(|p| p.start % ALIGNMENT == 0));
+
+        assert!(partitions.iter().all(|p| is_valid_guid(&p.uuid)));
+        assert_ne!(partitions[0].uuid, partitions[1].uuid);
+    }
+
+    #[test]
+    fn lays_out_mbr_partitions() {
+        let device = Path::new("/dev/sda");
+        let partitions = layout_partitions(
+            "seed",
+            device,
+            PartitionTable::Mbr,
+            &desktop_layout(),
+            100 * GIB,
+        )
+        .unwrap();
+        let signature = disk_id("seed", device, PartitionTable::Mbr);
+
+        assert_eq!(signature.len(), 8);
+        let types = partitions
+            .iter()
+            .map(|p| p.partition_type.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(types, [MBR_ESP_TYPE, MBR_SWAP_TYPE, MBR_LINUX_TYPE]);
+        assert_eq!(partitions[2].uuid, format!("{signature}-03"));
+        assert_eq!(partitions[0].name, None);
+        // MBR has no backup header at the end of the device
+        let last = &partitions[2];
+        assert_eq!(last.start + last.size, 100 * GIB);
+    }
+
+    #[test]
+    fn rejects_layouts_larger_than_the_device() {
+        let layout = layout(json!([
+            { "size": "8GiB", "filesystem": "Ext4", "mountpoint": "/" },
+            { "size": "8GiB", "filesystem": "Ext4", "mountpoint": "/home" },
+        ]));
+        let result = layout_partitions(
+            "seed",
+            Path::new("/dev/sda"),
+            PartitionTable::Gpt,
+            &layout,
+            16 * GIB,
+        );
+
+        assert!(result.unwrap_err().contains("only has"));
+    }
+
+    #[test]
+    fn rejects_layouts_without_space_for_the_rest() {
+        let layout = layout(json!([
+            { "size": "100%", "filesystem": "Ext4", "mountpoint": "/" },
+            { "size": "rest", "filesystem": "Ext4", "mountpoint": "/home" },
+        ]));
+        let result = layout_partitions(
+            "seed",
+            Path::new("/dev/sda"),
+            PartitionTable::Gpt,
+            &layout,
+            16 * GIB,
+        );
+
+        assert!(result.unwrap_err().contains("no space is left"));
+    }
+
+    #[test]
+    fn derives_ids_from_the_seed() {
+        let device = Path::new("/dev/sda1");
+
+        assert_eq!(
+            derive_uuid("seed", "partition", device),
+            derive_uuid("seed", "partition", device)
+        );
+        assert_ne!(
+            derive_uuid("seed", "partition", device),
+            derive_uuid("other seed", "partition", device)
+        );
+        assert_ne!(
+            derive_uuid("seed", "partition", device),
+            derive_uuid("seed", "filesystem", device)
+        );
+
+        let uuid = derive_uuid("seed", "partition", device);
+        assert!(is_valid_guid(&uuid));
+        assert_eq!(&uuid[14..15], "4");
+        assert!(matches!(&uuid[19..20], "8" | "9" | "a" | "b"));
+
+        let fat = filesystem_uuid("seed", device, &FileSystem::VFAT).unwrap();
+        assert_eq!(fat.len(), 9);
+        assert_eq!(fat, fat.to_uppercase());
+        assert_eq!(filesystem_uuid("seed", device, &FileSystem::Minix), None);
+    }
+
+    #[test]
+    fn passes_logical_volume_sizes_as_bytes_or_extents() {
+        let group: VolumeGroup = serde_json::from_value(json!({
+            "name": "vg",
+            "physical_volumes": ["/dev/md/system"],
+            "logical_volumes": [
+                { "name": "root", "size": "20GiB" },
+                { "name": "var", "size": "25%" },
+                { "name": "home", "size": "rest" },
+            ],
+        }))
+        .unwrap();
+        let mut builder = PlanBuilder::new("seed");
+        builder.volume_group(&group);
+
+        let sizes = builder
+            .build()
+            .steps
+            .into_iter()
+            .filter_map(|step| match step {
+                PlanStep::CreateLogicalVolume { size, extents, .. } => Some((size, extents)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            sizes,
+            [
+                (Some(20 * GIB), None),
+                (None, Some("25%VG".into())),
+                (None, Some("100%FREE".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn names_partition_devices() {
+        assert_eq!(
+            partition_device(Path::new("/dev/sda"), 2),
+            Path::new("/dev/sda2")
+        );
+        assert_eq!(
+            partition_device(Path::new("/dev/mmcblk0"), 1),
+            Path::new("/dev/mmcblk0p1")
+        );
+    }
+}
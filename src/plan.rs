@@ -0,0 +1,252 @@
+use std::{fmt, path::PathBuf};
+
+use crate::{
+    config::Config,
+    error::{AppError, AppResult},
+    graph::sort_steps,
+    scripting::loader::ScriptLoader,
+    tasks::*,
+};
+
+/// A step in the installation process
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum TaskStep {
+    Run(Task),
+    Skip(TaskFiles, SkipReason),
+}
+
+impl TaskStep {
+    pub fn files(&self) -> TaskFiles {
+        match self {
+            TaskStep::Run(task) => task.files(),
+            TaskStep::Skip(files, _) => files.clone(),
+        }
+    }
+}
+
+/// Returns the steps of an installation with the given config
+/// in the order they are executed in
+pub(crate) fn task_steps(
+    config: Config,
+    custom_tasks: Vec<TaskManifest>,
+    seed: &str,
+) -> AppResult<Vec<TaskStep>> {
+    use TaskStep::*;
+
+    // hashing the passwords once keeps the hashes and the config hash of the tasks stable
+    let config = config.with_hashed_passwords(seed);
+    let config_value = serde_json::to_value(&config)?;
+    let mut custom_args = config.custom_tasks;
+    let partition_plan = config.partitions.plan(seed)?;
+    let root = partition_plan.as_ref().and_then(|p| p.root_device());
+    let timeshift_args = InstallTimeshiftArgs::new(root.as_ref());
+    let resume = partition_plan.as_ref().and_then(|p| p.resume_device());
+    let kernel_cmdline = config
+        .initramfs
+        .kernel_params(root.as_ref(), resume.as_ref())
+        .into_iter()
+        .chain(config.kernel_cmdline)
+        .collect::<Vec<_>>();
+    let kernels = config.kernels.resolve(
+        config.initramfs.generator,
+        &kernel_cmdline,
+        config.bootloader.default_entry.as_deref(),
+    );
+    let bootloader_args = InstallBootloaderArgs::new(
+        config.bootloader,
+        root.clone(),
+        kernel_cmdline.clone(),
+        &kernels,
+    );
+    let kernels_args = InstallKernelsArgs {
+        initramfs: config.initramfs.resolve(root.as_ref(), resume.is_some()),
+        kernels,
+        kernel_cmdline,
+    };
+    let mut extra_packages = config.extra_packages;
+    if let Some(aur) = &mut extra_packages.aur {
+        aur.user = aur
+            .user
+            .take()
+            .or_else(|| config.users.users.first().map(|u| u.name.clone()));
+    }
+    let mut steps = vec![
+        Run(Task::CreatePartitions(CreatePartitionsArgs {
+            plan: partition_plan,
+            config: config.partitions,
+        })),
+        Run(Task::InstallBase(InstallBaseArgs {
+            exclude: extra_packages.exclude.clone(),
+        })),
+        Run(Task::InstallKernels(kernels_args)),
+        Run(Task::InstallBootloader(bootloader_args)),
+        Run(Task::ConfigureLocale(config.locale)),
+        Run(Task::ConfigureNetwork(config.network)),
+        if config.enable_zramd {
+            Run(Task::InstallZRamD(()))
+        } else {
+            Skip(
+                TaskFiles::of::<InstallZRamDScript>(),
+                SkipReason::Disabled("enable_zramd"),
+            )
+        },
+        if config.enable_timeshift {
+            Run(Task::InstallTimeshift(timeshift_args))
+        } else {
+            Skip(
+                TaskFiles::of::<InstallTimeshiftScript>(),
+                SkipReason::Disabled("enable_timeshift"),
+            )
+        },
+        if let Some(flatpak) = config.flatpak {
+            let default_user = config.users.users.first().map(|u| u.name.as_str());
+            Run(Task::InstallFlatpak(flatpak.resolve(default_user)))
+        } else {
+            Skip(
+                TaskFiles::of::<InstallFlatpakScript>(),
+                SkipReason::NotConfigured("flatpak"),
+            )
+        },
+        Run(Task::SetupUsers(config.users)),
+        Run(Task::SetupRootUser(config.root_user)),
+        Run(Task::InstallDesktop(config.desktop)),
+        Run(Task::InstallExtraPackages(extra_packages)),
+        if let Some(unakite) = config.unakite {
+            Run(Task::ConfigureUnakite(unakite))
+        } else {
+            Skip(
+                TaskFiles::of::<ConfigureUnakiteScript>(),
+                SkipReason::NotConfigured("unakite"),
+            )
+        },
+    ];
+
+    for manifest in custom_tasks {
+        if steps.iter().any(|s| s.files().name() == manifest.name) {
+            return Err(AppError::DuplicateTask(manifest.name));
+        }
+        let step = if manifest.condition_met(&config_value) {
+            let args = manifest.resolve_args(custom_args.remove(&manifest.name))?;
+            Run(Task::Custom(CustomTask { manifest, args }))
+        } else {
+            let condition = manifest.condition.clone().unwrap_or_default();
+            Skip(manifest.files(), SkipReason::ConditionNotMet(condition))
+        };
+        steps.push(step);
+    }
+
+    sort_steps(steps)
+}
+
+/// The reason a task is left out of the installation
+#[derive(Clone, Debug)]
+pub enum SkipReason {
+    /// The task is disabled by the given config flag
+    Disabled(&'static str),
+    /// The given config section is missing
+    NotConfigured(&'static str),
+    /// The condition of a user defined task points to an unset config value
+    ConditionNotMet(String),
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Disabled(flag) => write!(f, "{flag} = false"),
+            SkipReason::NotConfigured(section) => write!(f, "{section} is not configured"),
+            SkipReason::ConditionNotMet(condition) => write!(f, "{condition} is not set"),
+        }
+    }
+}
+
+/// A script or hook file that gets loaded for a task
+#[derive(Clone, Debug)]
+pub struct PlannedFile {
+    pub path: PathBuf,
+    pub exists: bool,
+}
+
+impl PlannedFile {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            exists: path.exists(),
+            path,
+        }
+    }
+}
+
+/// A task as it would be executed by the task executor
+#[derive(Clone, Debug)]
+pub struct PlannedTask {
+    pub name: String,
+    pub script: PlannedFile,
+    pub pre_hook: PlannedFile,
+    pub post_hook: PlannedFile,
+    /// The arguments passed to the script and hooks.
+    /// This is `None` if the task is skipped
+    pub args: Option<serde_json::Value>,
+    pub skipped: Option<SkipReason>,
+}
+
+impl PlannedTask {
+    pub(crate) fn new(step: TaskStep, loader: &ScriptLoader) -> serde_json::Result<Self> {
+        let (files, args, skipped) = match step {
+            TaskStep::Run(task) => (task.files(), Some(task.args()?), None),
+            TaskStep::Skip(files, reason) => (files, None, Some(reason)),
+        };
+
+        Ok(Self {
+            name: files.name().to_owned(),
+            script: PlannedFile::new(loader.script_path(files.script())),
+            pre_hook: PlannedFile::new(loader.hook_path(files.pre_hook())),
+            post_hook: PlannedFile::new(loader.hook_path(files.post_hook())),
+            args,
+            skipped,
+        })
+    }
+}
+
+/// The list of tasks an installation would execute
+#[derive(Clone, Debug)]
+pub struct InstallPlan {
+    pub tasks: Vec<PlannedTask>,
+}
+
+impl fmt::Display for InstallPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, task) in self.tasks.iter().enumerate() {
+            if let Some(reason) = &task.skipped {
+                writeln!(f, "{:>2}. {} (skipped: {reason})", i + 1, task.name)?;
+                continue;
+            }
+            writeln!(f, "{:>2}. {}", i + 1, task.name)?;
+
+            if task.script.exists {
+                writeln!(f, "    script:    {}", task.script.path.display())?;
+            } else {
+                writeln!(f, "    script:    MISSING {}", task.script.path.display())?;
+            }
+            write_hook(f, "pre hook: ", &task.pre_hook)?;
+            write_hook(f, "post hook:", &task.post_hook)?;
+
+            if let Some(args) = &task.args {
+                let args = serde_json::to_string_pretty(args).map_err(|_| fmt::Error)?;
+                writeln!(
+                    f,
+                    "    args:      {}",
+                    args.replace('\n', "\n               ")
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn write_hook(f: &mut fmt::Formatter<'_>, label: &str, hook: &PlannedFile) -> fmt::Result {
+    if hook.exists {
+        writeln!(f, "    {label} {}", hook.path.display())
+    } else {
+        writeln!(f, "    {label} none ({} not found)", hook.path.display())
+    }
+}
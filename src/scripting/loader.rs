@@ -1,15 +1,23 @@
 use std::path::PathBuf;
 
-use crate::error::{AppError, AppResult};
+use serde::Serialize;
 
-use super::script::{NuScript, Script};
+use crate::{
+    error::{AppError, AppResult},
+    tasks::TaskManifest,
+};
+
+use super::script::{NuScript, TaskArgs};
 
 /// A loader for nu script files
 pub struct ScriptLoader {
     script_dir: PathBuf,
     hook_dir: PathBuf,
+    task_dir: PathBuf,
 }
 
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum HookType {
     Pre,
     Post,
@@ -21,13 +29,24 @@ impl ScriptLoader {
         Self {
             script_dir: crate::utils::SCRIPT_PATH.to_owned(),
             hook_dir: crate::utils::HOOK_PATH.to_owned(),
+            task_dir: crate::utils::TASK_PATH.to_owned(),
+        }
+    }
+
+    /// Creates a script loader that reads the files from the given config dir
+    #[cfg(test)]
+    pub(crate) fn with_dir(dir: &std::path::Path) -> Self {
+        Self {
+            script_dir: dir.join("scripts"),
+            hook_dir: dir.join("hooks"),
+            task_dir: dir.join("tasks"),
         }
     }
 
-    /// Loads the given script file
+    /// Loads the script file with the given name
     #[tracing::instrument(level = "trace", skip_all)]
-    pub fn load<S: Script>(&self) -> AppResult<NuScript<S>> {
-        let script_path = self.script_dir.join(S::get_name());
+    pub fn load<A: TaskArgs>(&self, name: &str) -> AppResult<NuScript<A>> {
+        let script_path = self.script_path(name);
 
         if !script_path.exists() {
             Err(AppError::ScriptNotFound(script_path))
@@ -36,12 +55,9 @@ impl ScriptLoader {
         }
     }
 
-    pub fn load_hook<S: Script>(&self, hook_type: HookType) -> Option<NuScript<S>> {
-        let script_name = match hook_type {
-            HookType::Pre => S::get_pre_hook(),
-            HookType::Post => S::get_post_hook(),
-        };
-        let script_path = self.hook_dir.join(script_name);
+    /// Loads the hook file with the given name if it exists
+    pub fn load_hook<A: TaskArgs>(&self, name: &str) -> Option<NuScript<A>> {
+        let script_path = self.hook_path(name);
 
         if !script_path.exists() {
             None
@@ -49,4 +65,19 @@ impl ScriptLoader {
             Some(NuScript::new(script_path))
         }
     }
+
+    /// Returns the path the script with the given file name is loaded from
+    pub fn script_path(&self, name: &str) -> PathBuf {
+        self.script_dir.join(name)
+    }
+
+    /// Returns the path the hook with the given file name is loaded from
+    pub fn hook_path(&self, name: &str) -> PathBuf {
+        self.hook_dir.join(name)
+    }
+
+    /// Loads the manifests of all user defined tasks
+    pub fn load_custom_tasks(&self) -> AppResult<Vec<TaskManifest>> {
+        TaskManifest::load_all(&self.task_dir)
+    }
 }
@@ -1,18 +1,20 @@
 use core::fmt;
-use std::{collections::HashMap, marker::PhantomData, path::PathBuf};
+use std::{collections::HashMap, marker::PhantomData, panic, path::PathBuf};
 
 use embed_nu::{
     rusty_value::RustyValue, Argument, CommandGroupConfig, ContextBuilder, IntoArgument, IntoValue,
     RawValue, Value,
 };
-use tokio::fs;
+use nu_protocol::PipelineData;
+use serde::Serialize;
+use tokio::{fs, sync::mpsc, task};
 
 use crate::error::{AppError, AppResult};
 
 /// A trait implemented for a given nu script type to
 /// associate arguments
 pub trait Script {
-    type Args: ScriptArgs + fmt::Debug + Clone;
+    type Args: TaskArgs;
 
     /// Returns the (expected) name of the script file
     /// This function is used by the loader to load the associated file
@@ -26,6 +28,10 @@ pub trait Script {
     /// Returns the name of the script file that get's executed after
     /// the actual script. This has to be the full file name including the extension.
     fn get_post_hook() -> &'static str;
+
+    /// Returns the names of the tasks that need to be executed before this one.
+    /// The names don't include the file extension
+    fn get_dependencies() -> &'static [&'static str];
 }
 
 /// Script arguments that can be collected in a Vec to
@@ -46,14 +52,19 @@ impl<T: RustyValue> ScriptArgs for T {
     }
 }
 
-/// A nu script instance that can be executed
-pub struct NuScript<S: Script> {
+/// The arguments the script and hooks of a task are called with
+pub trait TaskArgs: ScriptArgs + fmt::Debug + Clone + Serialize + Send + 'static {}
+
+impl<T: ScriptArgs + fmt::Debug + Clone + Serialize + Send + 'static> TaskArgs for T {}
+
+/// A nu script instance that can be executed with the given type of arguments
+pub struct NuScript<A: TaskArgs> {
     path: PathBuf,
     vars: HashMap<String, Value>,
-    __phantom: PhantomData<S>,
+    __phantom: PhantomData<A>,
 }
 
-impl<S: Script> NuScript<S> {
+impl<A: TaskArgs> NuScript<A> {
     pub(crate) fn new(path: PathBuf) -> Self {
         Self {
             path,
@@ -69,20 +80,30 @@ impl<S: Script> NuScript<S> {
         self
     }
 
-    /// Executes the script with the given args
-    #[tracing::instrument(level = "trace", skip(self))]
-    pub async fn execute(&self, args: S::Args) -> AppResult<()> {
-        let mut ctx = ContextBuilder::default()
-            .with_command_groups(CommandGroupConfig::default().all_groups(true))
-            .add_script(self.read_file().await?)?
-            .build()?;
-
-        if ctx.has_fn("main") {
-            ctx.call_fn("main", args.get_args())?;
-            Ok(())
-        } else {
-            Err(AppError::MissingMain(self.path.clone()))
+    /// Executes the script with the given args.
+    /// The output is passed to the given function line by line while the script runs
+    #[tracing::instrument(level = "trace", skip(self, on_line))]
+    pub async fn execute<F: FnMut(String)>(&self, args: A, mut on_line: F) -> AppResult<()> {
+        let contents = self.read_file().await?;
+        let path = self.path.clone();
+        let vars = self.vars.clone();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        // nu evaluates the script synchronously. Running it on a blocking thread
+        // keeps the runtime free to deliver the lines while the script runs
+        let handle = task::spawn_blocking(move || {
+            evaluate(path, vars, contents, args, |line| {
+                // the receiver is only dropped once the script has finished
+                let _ = sender.send(line);
+            })
+        });
+
+        while let Some(line) = receiver.recv().await {
+            on_line(line);
         }
+
+        handle
+            .await
+            .unwrap_or_else(|e| panic::resume_unwind(e.into_panic()))
     }
 
     async fn read_file(&self) -> AppResult<String> {
@@ -90,6 +111,56 @@ impl<S: Script> NuScript<S> {
     }
 }
 
+/// Parses the script with the given global variables and calls its main function
+fn evaluate<A: TaskArgs, F: FnMut(String)>(
+    path: PathBuf,
+    vars: HashMap<String, Value>,
+    contents: String,
+    args: A,
+    mut on_line: F,
+) -> AppResult<()> {
+    let mut builder = ContextBuilder::default()
+        .with_command_groups(CommandGroupConfig::default().all_groups(true));
+
+    // the variables need to be declared before the script gets parsed
+    for (name, value) in vars {
+        builder = builder.add_var(name, RawValue(value))?;
+    }
+    let mut ctx = builder.add_script(contents)?.build()?;
+
+    if !ctx.has_fn("main") {
+        return Err(AppError::MissingMain(path));
+    }
+    let output = ctx.call_fn("main", args.get_args())?;
+    // external commands write chunks of their output while values are separate lines.
+    // nu reads the captured stderr of an external command to the end before its stdout,
+    // so the stdout of a command that keeps stderr open only arrives once it exits
+    let is_external = matches!(output, PipelineData::ExternalStream { .. });
+    let config = nu_protocol::Config::default();
+    let mut pending = String::new();
+
+    for value in output {
+        match value {
+            Value::Error { error } => return Err(embed_nu::Error::from(error).into()),
+            Value::Binary { val, .. } => pending.push_str(&String::from_utf8_lossy(&val)),
+            value => pending.push_str(&value.into_string("\n", &config)),
+        }
+        if !is_external {
+            pending.push('\n');
+        }
+        while let Some(index) = pending.find('\n') {
+            let line = pending[..index].trim_end_matches('\r').to_owned();
+            pending.drain(..=index);
+            on_line(line);
+        }
+    }
+    if !pending.is_empty() {
+        on_line(pending);
+    }
+
+    Ok(())
+}
+
 /// Defines a script
 /// This macro doesn't accept a file extension for the script name
 /// as it is reused for the hook name
@@ -98,6 +169,7 @@ macro_rules! script {
     ($script:ident {
         file = $name:literal
         args = $argtype:ident
+        $(after = [$($dependency:literal),*])?
     }) => {
         pub struct $script;
 
@@ -115,6 +187,44 @@ macro_rules! script {
             fn get_post_hook() -> &'static str {
                 concat!($name, ".post.nu")
             }
+
+            fn get_dependencies() -> &'static [&'static str] {
+                &[$($($dependency),*)?]
+            }
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn run(script: &str, args: String) -> AppResult<Vec<String>> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("script.nu");
+        std::fs::write(&path, script).unwrap();
+        let mut lines = Vec::new();
+        NuScript::new(path)
+            .set_global_var("GREETING", "hello")
+            .execute(args, |line| lines.push(line))
+            .await?;
+
+        Ok(lines)
+    }
+
+    #[tokio::test]
+    async fn passes_the_output_line_by_line() {
+        let lines = run("def main [name] { [$GREETING $name] }", "world".into())
+            .await
+            .unwrap();
+
+        assert_eq!(lines, ["hello", "world"]);
+    }
+
+    #[tokio::test]
+    async fn requires_a_main_function() {
+        let result = run("def other [] {}", String::new()).await;
+
+        assert!(matches!(result, Err(AppError::MissingMain(_))));
+    }
+}
@@ -0,0 +1,278 @@
+use std::fmt;
+
+use embed_nu::rusty_value::{Primitive, RustyValue, Value};
+use pwhash::HashSetup;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::validation::{Validate, Validator};
+
+/// The prefixes of the crypt(3) hash formats accepted as already hashed passwords
+const HASH_PREFIXES: &[&str] = &["$6$", "$5$", "$y$", "$gy$"];
+
+const REDACTED: &str = "<redacted>";
+
+/// The characters crypt(3) salts consist of
+const SALT_CHARS: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+const SALT_LENGTH: usize = 16;
+
+const REDACTED_MESSAGE: &str =
+    "the value was redacted when the config was rendered and needs to be set again";
+
+/// A password given either in plain text or as a crypt(3) hash
+/// (e.g. `$6$` for SHA-512 or `$y$` for yescrypt).
+/// Plain passwords are hashed with SHA-512 before scripts get to see them
+/// and passwords are never included in debug output.
+/// The `<redacted>` placeholder written by `config render` is read back as [Password::Redacted]
+#[derive(Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
+pub enum Password {
+    Plain(String),
+    Hashed(String),
+    /// A password removed from the config passed to tasks without access to secrets
+    Redacted,
+}
+
+impl Password {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Password::Plain(p) | Password::Hashed(p) => p.is_empty(),
+            Password::Redacted => false,
+        }
+    }
+
+    /// Hashes a plain password with a salt derived from the seed of the installation
+    /// and the given purpose, so resumed installations pass the same hash to the scripts
+    pub fn hashed(self, seed: &str, purpose: &str) -> Self {
+        match self {
+            Password::Plain(p) => {
+                let salt = derive_salt(seed, purpose);
+                let setup = HashSetup {
+                    salt: Some(&salt),
+                    rounds: None,
+                };
+
+                Password::Hashed(
+                    pwhash::sha512_crypt::hash_with(setup, p)
+                        .expect("the derived salt only contains valid characters"),
+                )
+            }
+            password => password,
+        }
+    }
+
+    /// Returns the crypt(3) hash of the password.
+    /// Plain passwords get a new random salt every time
+    pub fn hash(&self) -> Option<String> {
+        match self {
+            Password::Plain(p) => Some(
+                pwhash::sha512_crypt::hash(p).expect("the default SHA-512 parameters are valid"),
+            ),
+            Password::Hashed(h) => Some(h.to_owned()),
+            Password::Redacted => None,
+        }
+    }
+}
+
+impl From<String> for Password {
+    fn from(s: String) -> Self {
+        if s == REDACTED {
+            Self::Redacted
+        } else if HASH_PREFIXES.iter().any(|p| s.starts_with(p)) {
+            Self::Hashed(s)
+        } else {
+            Self::Plain(s)
+        }
+    }
+}
+
+impl From<Password> for String {
+    fn from(p: Password) -> Self {
+        match p {
+            Password::Plain(p) | Password::Hashed(p) => p,
+            Password::Redacted => REDACTED.into(),
+        }
+    }
+}
+
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl RustyValue for Password {
+    fn into_rusty_value(self) -> Value {
+        match self.hash() {
+            Some(hash) => Value::Primitive(Primitive::String(hash)),
+            None => Value::None,
+        }
+    }
+}
+
+impl JsonSchema for Password {
+    fn schema_name() -> String {
+        "Password".into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+impl Validate for Password {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        match self {
+            Password::Plain(p) if p.is_empty() => {
+                validator.error(path, "the password must not be empty")
+            }
+            // $id$salt$hash with an optional $rounds=n$ or parameter field
+            Password::Hashed(h) if h.split('$').count() < 4 || h.ends_with('$') => {
+                validator.error(path, "the password hash is malformed")
+            }
+            Password::Redacted => validator.error(path, REDACTED_MESSAGE),
+            _ => {}
+        }
+    }
+}
+
+/// A secret that scripts need in plain text, e.g. a disk encryption passphrase.
+/// It is never included in debug output
+#[derive(Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
+pub enum Secret {
+    Plain(String),
+    /// A secret removed from the config passed to tasks without access to secrets
+    Redacted,
+}
+
+impl From<String> for Secret {
+    fn from(s: String) -> Self {
+        if s == REDACTED {
+            Self::Redacted
+        } else {
+            Self::Plain(s)
+        }
+    }
+}
+
+impl From<Secret> for String {
+    fn from(s: Secret) -> Self {
+        match s {
+            Secret::Plain(s) => s,
+            Secret::Redacted => REDACTED.into(),
+        }
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl RustyValue for Secret {
+    fn into_rusty_value(self) -> Value {
+        match self {
+            Secret::Plain(s) => Value::Primitive(Primitive::String(s)),
+            Secret::Redacted => Value::None,
+        }
+    }
+}
+
+impl JsonSchema for Secret {
+    fn schema_name() -> String {
+        "Secret".into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+impl Validate for Secret {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        match self {
+            Secret::Plain(s) if s.is_empty() => {
+                validator.error(path, "the secret must not be empty")
+            }
+            Secret::Redacted => validator.error(path, REDACTED_MESSAGE),
+            _ => {}
+        }
+    }
+}
+
+/// Derives a crypt(3) salt from the seed of the installation and the purpose
+fn derive_salt(seed: &str, purpose: &str) -> String {
+    Sha256::digest(format!("{seed}:{purpose}"))
+        .iter()
+        .take(SALT_LENGTH)
+        .map(|b| SALT_CHARS[*b as usize % SALT_CHARS.len()] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "$6$saltsalt$qFmFH.bQmmtXzyBY0s9v7Oicd2z4XSIecDzlB5KiA2/jctKu9YterLp8wwnSq.qc.eoxqOmSuNp2xS0ktL3nh/";
+
+    fn round_trip(password: &Password) -> Password {
+        serde_json::from_str(&serde_json::to_string(password).unwrap()).unwrap()
+    }
+
+    fn errors(password: &Password) -> usize {
+        let mut validator = Validator::default();
+        validator.validate("$", password);
+
+        validator.into_errors().len()
+    }
+
+    #[test]
+    fn round_trips_plain_passwords() {
+        let password = Password::from("hunter2".to_owned());
+
+        assert!(password == Password::Plain("hunter2".into()));
+        assert!(round_trip(&password) == password);
+        assert_eq!(errors(&password), 0);
+    }
+
+    #[test]
+    fn round_trips_hashed_passwords() {
+        let password = Password::from(HASH.to_owned());
+
+        assert!(password == Password::Hashed(HASH.into()));
+        assert!(round_trip(&password) == password);
+        assert_eq!(password.hash().as_deref(), Some(HASH));
+        assert_eq!(errors(&password), 0);
+        assert_eq!(errors(&Password::from("$6$salt$".to_owned())), 1);
+    }
+
+    #[test]
+    fn reads_redacted_passwords_back_as_redacted() {
+        assert_eq!(String::from(Password::Redacted), REDACTED);
+        assert!(round_trip(&Password::Redacted) == Password::Redacted);
+        assert_eq!(Password::Redacted.hash(), None);
+        assert_eq!(errors(&Password::Redacted), 1);
+
+        let secret: Secret = serde_json::from_str("\"<redacted>\"").unwrap();
+        assert!(secret == Secret::Redacted);
+    }
+
+    #[test]
+    fn hashes_passwords_once_per_seed() {
+        let password = Password::from("hunter2".to_owned());
+        let hashed = password.clone().hashed("seed", "password:root");
+
+        let Password::Hashed(hash) = &hashed else {
+            panic!("the password wasn't hashed");
+        };
+        assert!(pwhash::sha512_crypt::verify("hunter2", hash));
+        assert!(password.clone().hashed("seed", "password:root") == hashed);
+        assert!(password.clone().hashed("other seed", "password:root") != hashed);
+        assert!(password.hashed("seed", "password:alice") != hashed);
+        assert!(hashed.clone().hashed("seed", "password:root") == hashed);
+    }
+}
@@ -1,16 +1,83 @@
 use embed_nu::rusty_value::*;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
-use crate::script;
+use crate::{
+    locales::{closest_match, LocaleData},
+    script,
+    validation::{Validate, Validator},
+};
 
 script!(ConfigureLocaleScript {
     file = "configure-locale"
     args = LocaleConfig
+    after = ["install-base"]
 });
 
-#[derive(Clone, Deserialize, RustyValue, Debug)]
+#[derive(Clone, Deserialize, Serialize, RustyValue, Debug, JsonSchema)]
 pub struct LocaleConfig {
+    /// The locales to generate, e.g. `en_US.UTF-8` or `en_US.UTF-8 UTF-8`
     pub locale: Vec<String>,
     pub keymap: String,
     pub timezone: String,
 }
+
+impl Validate for LocaleConfig {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        // values can only be checked if the lists are available on this system
+        let data = LocaleData::default();
+
+        if self.locale.is_empty() {
+            validator.error(format!("{path}.locale"), "at least one locale is required");
+        }
+        if let Ok(supported) = data.locales() {
+            // locales can be given with or without the charset
+            let names = supported
+                .iter()
+                .filter_map(|l| l.split_whitespace().next())
+                .collect::<Vec<_>>();
+
+            for (i, locale) in self.locale.iter().enumerate() {
+                if !supported.contains(locale) && !names.contains(&locale.as_str()) {
+                    validator.error(
+                        format!("{path}.locale[{i}]"),
+                        unknown("locale", locale, closest_match(locale, &names)),
+                    );
+                }
+            }
+        }
+        if self.keymap.is_empty() {
+            validator.error(format!("{path}.keymap"), "the keymap must not be empty");
+        } else if let Ok(keymaps) = data.keymaps() {
+            if !keymaps.contains(&self.keymap) {
+                validator.error(
+                    format!("{path}.keymap"),
+                    unknown(
+                        "keymap",
+                        &self.keymap,
+                        closest_match(&self.keymap, &keymaps),
+                    ),
+                );
+            }
+        }
+        if let Ok(timezones) = data.timezones() {
+            if !timezones.contains(&self.timezone) {
+                validator.error(
+                    format!("{path}.timezone"),
+                    unknown(
+                        "timezone",
+                        &self.timezone,
+                        closest_match(&self.timezone, &timezones),
+                    ),
+                );
+            }
+        }
+    }
+}
+
+fn unknown(kind: &str, value: &str, suggestion: Option<&str>) -> String {
+    match suggestion {
+        Some(suggestion) => format!("unknown {kind} `{value}`. Did you mean `{suggestion}`?"),
+        None => format!("unknown {kind} `{value}`"),
+    }
+}
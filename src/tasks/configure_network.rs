@@ -1,15 +1,293 @@
+use std::collections::HashSet;
+
 use embed_nu::rusty_value::*;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    script,
+    secrets::Secret,
+    validation::{is_valid_cidr, is_valid_hostname, is_valid_ip, Validate, Validator},
+};
 
-use crate::script;
+/// The maximum length of a network interface name in the kernel
+const MAX_INTERFACE_NAME_LEN: usize = 15;
 
 script!(ConfigureNetworkScript {
     file = "configure-network"
     args = NetworkConfig
+    after = ["install-base"]
 });
 
-#[derive(Clone, Debug, Deserialize, RustyValue)]
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
 pub struct NetworkConfig {
     pub hostname: String,
     pub ipv6_loopback: bool,
+    /// The service managing the network connections
+    #[serde(default)]
+    pub backend: NetworkBackend,
+    /// Interfaces that aren't configured are left to the defaults of the backend
+    #[serde(default)]
+    pub interfaces: Vec<NetworkInterface>,
+}
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, RustyValue, JsonSchema,
+)]
+pub enum NetworkBackend {
+    #[default]
+    NetworkManager,
+    SystemdNetworkd,
+    /// iwd with its built-in network configuration. Only manages wireless interfaces
+    Iwd,
+    /// No network service is installed
+    None,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct NetworkInterface {
+    /// The name of the interface, e.g. `enp1s0` or `wlan0`
+    pub name: String,
+    #[serde(flatten)]
+    pub ip: IpConfig,
+    /// Connects the interface to a wireless network
+    pub wifi: Option<WifiConfig>,
+    /// Tagged VLANs on top of the interface
+    #[serde(default)]
+    pub vlans: Vec<Vlan>,
+}
+
+/// The addressing of an interface or VLAN
+#[derive(Clone, Debug, Default, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct IpConfig {
+    #[serde(default)]
+    pub addressing: Addressing,
+    /// The addresses in CIDR notation, e.g. `192.168.1.10/24` or `fd00::10/64`
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    pub gateway: Option<String>,
+    /// DNS servers. DHCP servers are used in addition to them
+    #[serde(default)]
+    pub dns: Vec<String>,
+    #[serde(default)]
+    pub search_domains: Vec<String>,
+}
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, RustyValue, JsonSchema,
+)]
+pub enum Addressing {
+    #[default]
+    Dhcp,
+    Static,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct WifiConfig {
+    pub ssid: String,
+    /// The pre-shared key for WPA networks. Open networks don't have one
+    pub psk: Option<Secret>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct Vlan {
+    pub id: u16,
+    /// The name of the VLAN interface. Defaults to `<interface>.<id>`
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub ip: IpConfig,
+}
+
+impl NetworkConfig {
+    /// Removes the Wi-Fi keys
+    pub(crate) fn redact(&mut self) {
+        self.interfaces
+            .iter_mut()
+            .filter_map(|i| i.wifi.as_mut())
+            .filter(|w| w.psk.is_some())
+            .for_each(|w| w.psk = Some(Secret::Redacted));
+    }
+}
+
+impl Validate for NetworkConfig {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        if !is_valid_hostname(&self.hostname) {
+            validator.error(
+                format!("{path}.hostname"),
+                format!("`{}` is not a valid hostname", self.hostname),
+            );
+        }
+        if self.backend == NetworkBackend::None && !self.interfaces.is_empty() {
+            validator.error(
+                format!("{path}.interfaces"),
+                "interfaces can't be configured without a network backend",
+            );
+        }
+        let mut names = HashSet::new();
+
+        for (i, interface) in self.interfaces.iter().enumerate() {
+            let interface_path = format!("{path}.interfaces[{i}]");
+            validator.validate(&interface_path, interface);
+
+            let vlan_names = interface.vlans.iter().enumerate().map(|(j, vlan)| {
+                (
+                    format!("{interface_path}.vlans[{j}].name"),
+                    vlan.name
+                        .clone()
+                        .unwrap_or_else(|| format!("{}.{}", interface.name, vlan.id)),
+                )
+            });
+            for (name_path, name) in
+                std::iter::once((format!("{interface_path}.name"), interface.name.clone()))
+                    .chain(vlan_names)
+            {
+                if names.contains(&name) {
+                    validator.error(
+                        name_path,
+                        format!("the interface {name} is configured more than once"),
+                    );
+                }
+                names.insert(name);
+            }
+            if self.backend == NetworkBackend::Iwd {
+                if interface.wifi.is_none() {
+                    validator.error(
+                        format!("{interface_path}.wifi"),
+                        "iwd only manages wireless interfaces",
+                    );
+                }
+                if !interface.vlans.is_empty() {
+                    validator.error(
+                        format!("{interface_path}.vlans"),
+                        "iwd doesn't support VLANs",
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl Validate for NetworkInterface {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        if !is_valid_interface_name(&self.name) {
+            validator.error(
+                format!("{path}.name"),
+                format!("`{}` is not a valid interface name", self.name),
+            );
+        }
+        validator.validate(path, &self.ip);
+
+        if let Some(wifi) = &self.wifi {
+            validator.validate(&format!("{path}.wifi"), wifi);
+        }
+        let mut ids = HashSet::new();
+
+        for (i, vlan) in self.vlans.iter().enumerate() {
+            let vlan_path = format!("{path}.vlans[{i}]");
+            validator.validate(&vlan_path, vlan);
+
+            if !ids.insert(vlan.id) {
+                validator.error(
+                    format!("{vlan_path}.id"),
+                    format!("the VLAN {} is configured more than once", vlan.id),
+                );
+            }
+        }
+    }
+}
+
+impl Validate for IpConfig {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        match self.addressing {
+            Addressing::Static if self.addresses.is_empty() => validator.error(
+                format!("{path}.addresses"),
+                "static addressing requires at least one address",
+            ),
+            Addressing::Dhcp if !self.addresses.is_empty() || self.gateway.is_some() => validator
+                .error(
+                    format!("{path}.addressing"),
+                    "addresses and gateways can only be set with static addressing",
+                ),
+            _ => {}
+        }
+        for (i, address) in self.addresses.iter().enumerate() {
+            if !is_valid_cidr(address) {
+                validator.error(
+                    format!("{path}.addresses[{i}]"),
+                    format!("`{address}` is not an IP address with a prefix length"),
+                );
+            }
+        }
+        if let Some(gateway) = self.gateway.as_ref().filter(|g| !is_valid_ip(g)) {
+            validator.error(
+                format!("{path}.gateway"),
+                format!("`{gateway}` is not a valid IP address"),
+            );
+        }
+        for (i, server) in self.dns.iter().enumerate() {
+            if !is_valid_ip(server) {
+                validator.error(
+                    format!("{path}.dns[{i}]"),
+                    format!("`{server}` is not a valid IP address"),
+                );
+            }
+        }
+        for (i, domain) in self.search_domains.iter().enumerate() {
+            if !is_valid_hostname(domain) {
+                validator.error(
+                    format!("{path}.search_domains[{i}]"),
+                    format!("`{domain}` is not a valid domain"),
+                );
+            }
+        }
+    }
+}
+
+impl Validate for WifiConfig {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        if self.ssid.is_empty() || self.ssid.len() > 32 {
+            validator.error(
+                format!("{path}.ssid"),
+                "the SSID must be between 1 and 32 bytes long",
+            );
+        }
+        if let Some(Secret::Plain(psk)) = &self.psk {
+            // a passphrase or the key itself as 64 hex digits
+            let is_key = psk.len() == 64 && psk.chars().all(|c| c.is_ascii_hexdigit());
+            let is_passphrase = (8..=63).contains(&psk.len()) && psk.is_ascii();
+
+            if !is_key && !is_passphrase {
+                validator.error(
+                    format!("{path}.psk"),
+                    "the key must be a passphrase of 8 to 63 characters or 64 hex digits",
+                );
+            }
+        }
+    }
+}
+
+impl Validate for Vlan {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        if !(1..=4094).contains(&self.id) {
+            validator.error(
+                format!("{path}.id"),
+                "the VLAN id must be between 1 and 4094",
+            );
+        }
+        if let Some(name) = self.name.as_ref().filter(|n| !is_valid_interface_name(n)) {
+            validator.error(
+                format!("{path}.name"),
+                format!("`{name}` is not a valid interface name"),
+            );
+        }
+        validator.validate(path, &self.ip);
+    }
+}
+
+fn is_valid_interface_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_INTERFACE_NAME_LEN
+        && name != "."
+        && name != ".."
+        && !name.contains(|c: char| c == '/' || c == ':' || c.is_whitespace())
 }
@@ -1,19 +1,40 @@
 use std::path::PathBuf;
 
 use embed_nu::rusty_value::*;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
-use crate::script;
+use crate::{
+    script,
+    validation::{Validate, Validator},
+};
 
 script!(ConfigureUnakiteScript {
     file = "configure-unakite"
     args = UnakiteConfig
+    after = ["install-bootloader"]
 });
 
-#[derive(Clone, Debug, RustyValue, Deserialize)]
+#[derive(Clone, Debug, RustyValue, Deserialize, Serialize, JsonSchema)]
 pub struct UnakiteConfig {
     pub root: PathBuf,
     pub old_root: PathBuf,
     pub efidir: PathBuf,
     pub bootdev: PathBuf,
 }
+
+impl Validate for UnakiteConfig {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        let paths = [
+            ("root", &self.root),
+            ("old_root", &self.old_root),
+            ("efidir", &self.efidir),
+            ("bootdev", &self.bootdev),
+        ];
+        for (name, value) in paths {
+            if !value.is_absolute() {
+                validator.error(format!("{path}.{name}"), "the path must be absolute");
+            }
+        }
+    }
+}
@@ -1,36 +1,160 @@
-use std::path::PathBuf;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
 
 use embed_nu::rusty_value::*;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
-use crate::script;
+use crate::{
+    btrfs::BtrfsConfig,
+    devices::{find_partition, BlockDevice, DeviceScanner},
+    encryption::EncryptionConfig,
+    error::AppResult,
+    fstab::{Fstab, FstabIdentifier},
+    partitioning::{
+        is_valid_guid, layout_partitions, lv_device, partition_device, raid_device, PartitionPlan,
+        PartitionSize, PlanBuilder,
+    },
+    script,
+    validation::{Validate, Validator},
+};
 
 script!(CreatePartitionsScript {
     file = "create-partitions"
-    args = PartitionsConfig
+    args = CreatePartitionsArgs
 });
 
-#[derive(Clone, Debug, Deserialize, RustyValue)]
+/// The arguments passed to the create-partitions script
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue)]
+pub struct CreatePartitionsArgs {
+    pub config: PartitionsConfig,
+    /// The resolved steps to set up the storage. Not set for [Partitions::Auto]
+    pub plan: Option<PartitionPlan>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
 pub struct PartitionsConfig {
     pub device: PathBuf,
     pub efi_partition: bool,
+    /// The partition table created for [Partitions::Layout]
+    #[serde(default)]
+    pub table: PartitionTable,
     pub partitions: Partitions,
+    /// Additional disks that are repartitioned with their own layout
+    #[serde(default)]
+    pub disks: Vec<Disk>,
+    /// Software RAID arrays created from partitions of the disks
+    #[serde(default)]
+    pub raid_arrays: Vec<RaidArray>,
+    /// LVM volume groups created on partitions, RAID arrays or encrypted devices
+    #[serde(default)]
+    pub volume_groups: Vec<VolumeGroup>,
+    /// How filesystems are referred to in the generated fstab
+    #[serde(default)]
+    pub fstab_identifier: FstabIdentifier,
 }
 
-#[derive(Clone, Debug, Deserialize, RustyValue)]
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
 pub enum Partitions {
     Auto,
+    /// Uses already existing block devices
     Manual(Vec<Partition>),
+    /// Repartitions the device with the given partitions in order
+    Layout(Vec<PartitionSpec>),
+}
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, RustyValue, JsonSchema,
+)]
+pub enum PartitionTable {
+    #[default]
+    Gpt,
+    Mbr,
 }
 
-#[derive(Clone, Debug, Deserialize, RustyValue)]
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
 pub struct Partition {
     pub mountpoint: PathBuf,
     pub blockdevice: PathBuf,
     pub filesystem: Option<FileSystem>,
+    pub encryption: Option<EncryptionConfig>,
+    pub btrfs: Option<BtrfsConfig>,
+}
+
+/// A partition of a [Partitions::Layout]
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct PartitionSpec {
+    pub size: PartitionSize,
+    /// The GPT partition type. Derived from the mountpoint and filesystem by default
+    pub type_guid: Option<String>,
+    #[serde(flatten)]
+    pub volume: VolumeConfig,
 }
 
-#[derive(Clone, Debug, Deserialize, RustyValue)]
+/// How a partition, RAID array or logical volume is used
+#[derive(Clone, Debug, Default, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct VolumeConfig {
+    pub filesystem: Option<FileSystem>,
+    pub mountpoint: Option<PathBuf>,
+    /// The filesystem label. Also used as the partition name on GPT
+    pub label: Option<String>,
+    #[serde(default)]
+    pub mount_options: Vec<String>,
+    pub encryption: Option<EncryptionConfig>,
+    /// The subvolumes and mount options if the filesystem is btrfs
+    pub btrfs: Option<BtrfsConfig>,
+}
+
+/// An additional disk partitioned with its own layout
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct Disk {
+    pub device: PathBuf,
+    #[serde(default)]
+    pub table: PartitionTable,
+    pub layout: Vec<PartitionSpec>,
+}
+
+/// A software RAID array available as `/dev/md/<name>`
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct RaidArray {
+    pub name: String,
+    pub level: RaidLevel,
+    /// The member devices, e.g. `/dev/sda2`
+    pub devices: Vec<PathBuf>,
+    #[serde(flatten)]
+    pub volume: VolumeConfig,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub enum RaidLevel {
+    Raid0,
+    Raid1,
+    Raid5,
+    Raid6,
+    Raid10,
+}
+
+/// An LVM volume group with its logical volumes available as `/dev/<group>/<volume>`
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct VolumeGroup {
+    pub name: String,
+    /// The devices used as physical volumes, e.g. `/dev/md/system`
+    pub physical_volumes: Vec<PathBuf>,
+    pub logical_volumes: Vec<LogicalVolume>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct LogicalVolume {
+    pub name: String,
+    /// The size of the volume. Percentages are relative to the size of the volume group
+    pub size: PartitionSize,
+    #[serde(flatten)]
+    pub volume: VolumeConfig,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, RustyValue, JsonSchema)]
 pub enum FileSystem {
     VFAT,
     BFS,
@@ -44,4 +168,664 @@ pub enum FileSystem {
     BTRFS,
     Minix,
     F2FS,
+    Swap,
+}
+
+impl PartitionsConfig {
+    /// Resolves the partitioning into the ordered steps executed by the create-partitions script.
+    /// The sizes of the repartitioned disks are read from sysfs and the IDs of the created
+    /// partitions and filesystems are derived from the seed
+    pub fn plan(&self, seed: &str) -> AppResult<Option<PartitionPlan>> {
+        let mut builder = PlanBuilder::new(seed);
+
+        match &self.partitions {
+            Partitions::Auto => return Ok(None),
+            Partitions::Manual(partitions) => partitions
+                .iter()
+                .for_each(|p| builder.volume(&p.blockdevice, &p.volume())),
+            Partitions::Layout(layout) => builder.disk(&self.device, self.table, layout)?,
+        }
+        for disk in &self.disks {
+            builder.disk(&disk.device, disk.table, &disk.layout)?;
+        }
+        self.raid_arrays.iter().for_each(|a| builder.raid(a));
+        self.volume_groups
+            .iter()
+            .for_each(|g| builder.volume_group(g));
+
+        Ok(Some(builder.build()))
+    }
+
+    /// Generates the fstab of the installed system. Not available for [Partitions::Auto]
+    pub fn fstab(&self, seed: &str) -> AppResult<Option<Fstab>> {
+        let plan = match self.plan(seed)? {
+            Some(plan) => plan,
+            None => return Ok(None),
+        };
+        // only manual partitions can reuse existing filesystems
+        let existing = match self.partitions {
+            Partitions::Manual(_) => DeviceScanner::default().scan().unwrap_or_default(),
+            _ => Vec::new(),
+        };
+
+        Ok(Some(Fstab::new(&plan, self.fstab_identifier, &existing)))
+    }
+
+    /// Returns the mountpoint of the EFI system partition. Unknown for [Partitions::Auto]
+    pub fn esp_mountpoint(&self) -> Option<PathBuf> {
+        self.targets("$")
+            .into_iter()
+            .find(|t| t.is_esp)
+            .and_then(|t| t.volume.mountpoint)
+    }
+
+    /// Returns if the layout of the device contains a BIOS boot partition
+    pub fn has_bios_boot_partition(&self) -> bool {
+        match &self.partitions {
+            Partitions::Layout(layout) => layout.iter().any(PartitionSpec::is_bios_boot),
+            _ => false,
+        }
+    }
+
+    /// Removes the encryption passphrases
+    pub(crate) fn redact(&mut self) {
+        let encryptions = match &mut self.partitions {
+            Partitions::Auto => Vec::new(),
+            Partitions::Manual(partitions) => partitions
+                .iter_mut()
+                .filter_map(|p| p.encryption.as_mut())
+                .collect(),
+            Partitions::Layout(layout) => layout
+                .iter_mut()
+                .filter_map(|p| p.volume.encryption.as_mut())
+                .collect(),
+        };
+        encryptions
+            .into_iter()
+            .chain(
+                self.disks
+                    .iter_mut()
+                    .flat_map(|d| &mut d.layout)
+                    .filter_map(|p| p.volume.encryption.as_mut()),
+            )
+            .chain(
+                self.raid_arrays
+                    .iter_mut()
+                    .filter_map(|a| a.volume.encryption.as_mut()),
+            )
+            .chain(
+                self.volume_groups
+                    .iter_mut()
+                    .flat_map(|g| &mut g.logical_volumes)
+                    .filter_map(|v| v.volume.encryption.as_mut()),
+            )
+            .for_each(EncryptionConfig::redact);
+    }
+
+    /// Returns all devices that get formatted, mounted or encrypted
+    /// together with the config path they are defined at
+    fn targets(&self, path: &str) -> Vec<Target> {
+        let mut targets = Vec::new();
+        match &self.partitions {
+            Partitions::Auto => {}
+            Partitions::Manual(partitions) => {
+                for (i, partition) in partitions.iter().enumerate() {
+                    let volume = partition.volume();
+                    targets.push(Target {
+                        path: format!("{path}.partitions.Manual[{i}]"),
+                        device: partition.blockdevice.clone(),
+                        is_esp: volume.is_esp_mount(),
+                        keeps_filesystem: volume.filesystem.is_none(),
+                        volume,
+                    });
+                }
+            }
+            Partitions::Layout(layout) => targets.extend(layout_targets(
+                &format!("{path}.partitions.Layout"),
+                &self.device,
+                layout,
+            )),
+        }
+        for (i, disk) in self.disks.iter().enumerate() {
+            targets.extend(layout_targets(
+                &format!("{path}.disks[{i}].layout"),
+                &disk.device,
+                &disk.layout,
+            ));
+        }
+        for (i, array) in self.raid_arrays.iter().enumerate() {
+            targets.push(Target {
+                path: format!("{path}.raid_arrays[{i}]"),
+                device: raid_device(&array.name),
+                volume: array.volume.clone(),
+                is_esp: false,
+                keeps_filesystem: false,
+            });
+        }
+        for (i, group) in self.volume_groups.iter().enumerate() {
+            for (j, volume) in group.logical_volumes.iter().enumerate() {
+                targets.push(Target {
+                    path: format!("{path}.volume_groups[{i}].logical_volumes[{j}]"),
+                    device: lv_device(&group.name, &volume.name),
+                    volume: volume.volume.clone(),
+                    is_esp: false,
+                    keeps_filesystem: false,
+                });
+            }
+        }
+
+        targets
+    }
+}
+
+impl Partition {
+    /// Returns the usage of the partition
+    pub fn volume(&self) -> VolumeConfig {
+        VolumeConfig {
+            filesystem: self.filesystem.clone(),
+            mountpoint: Some(self.mountpoint.clone()),
+            label: None,
+            mount_options: Vec::new(),
+            encryption: self.encryption.clone(),
+            btrfs: self.btrfs.clone(),
+        }
+    }
+}
+
+impl VolumeConfig {
+    pub fn is_swap(&self) -> bool {
+        self.filesystem == Some(FileSystem::Swap)
+    }
+
+    /// Returns the mountpoint of the volume and all of its btrfs subvolumes
+    pub fn mountpoints(&self) -> impl Iterator<Item = &Path> {
+        self.mountpoint.as_deref().into_iter().chain(
+            self.btrfs
+                .iter()
+                .flat_map(|b| &b.subvolumes)
+                .filter_map(|s| s.mountpoint.as_deref()),
+        )
+    }
+
+    pub fn is_mounted_at<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.mountpoints().any(|m| m == path.as_ref())
+    }
+
+    /// Returns if the mountpoint is used for EFI system partitions.
+    /// A FAT filesystem mounted at `/boot` is treated as ESP as well
+    pub fn is_esp_mount(&self) -> bool {
+        let is_fat = matches!(self.filesystem, Some(FileSystem::VFAT | FileSystem::FAT));
+
+        self.is_mounted_at("/boot/efi")
+            || self.is_mounted_at("/efi")
+            || (is_fat && self.is_mounted_at("/boot"))
+    }
+
+    /// Returns the encrypted mapper device name if the volume is encrypted
+    fn mapper_name(&self, device: &Path) -> Option<String> {
+        self.encryption
+            .as_ref()
+            .map(|e| e.mapper_name(device, self.is_mounted_at("/")))
+    }
+
+    /// Returns if the volume is used by itself instead of being part of another device
+    fn is_used(&self) -> bool {
+        self.filesystem.is_some()
+            || self.mountpoints().next().is_some()
+            || self.encryption.is_some()
+    }
+}
+
+impl RaidLevel {
+    pub fn min_devices(&self) -> usize {
+        match self {
+            RaidLevel::Raid0 | RaidLevel::Raid1 => 2,
+            RaidLevel::Raid5 => 3,
+            RaidLevel::Raid6 | RaidLevel::Raid10 => 4,
+        }
+    }
+}
+
+/// A device that gets formatted, mounted or encrypted
+struct Target {
+    path: String,
+    device: PathBuf,
+    volume: VolumeConfig,
+    is_esp: bool,
+    /// Manual partitions without a filesystem keep their existing one
+    keeps_filesystem: bool,
+}
+
+impl Validate for PartitionsConfig {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        let scanner = DeviceScanner::default();
+        let mut device = None;
+
+        if self.device.as_os_str().is_empty() {
+            validator.error(format!("{path}.device"), "the device must not be empty");
+        } else {
+            device = validate_device_exists(
+                &format!("{path}.device"),
+                &self.device,
+                &scanner,
+                validator,
+            );
+        }
+        if let Partitions::Auto = self.partitions {
+            if !self.disks.is_empty()
+                || !self.raid_arrays.is_empty()
+                || !self.volume_groups.is_empty()
+            {
+                validator.error(
+                    format!("{path}.partitions"),
+                    "additional disks, RAID arrays and volume groups require a Manual or Layout partitioning",
+                );
+            }
+            return;
+        }
+        if let Partitions::Manual(partitions) = &self.partitions {
+            for (i, partition) in partitions.iter().enumerate() {
+                if partition.blockdevice.as_os_str().is_empty() {
+                    validator.error(
+                        format!("{path}.partitions.Manual[{i}].blockdevice"),
+                        "the block device must not be empty",
+                    );
+                }
+            }
+        }
+        let mut disks = HashSet::new();
+
+        if let Partitions::Layout(layout) = &self.partitions {
+            disks.insert(&self.device);
+            validate_layout(
+                &format!("{path}.partitions.Layout"),
+                device.as_ref(),
+                self.table,
+                layout,
+                validator,
+            );
+        }
+        for (i, disk) in self.disks.iter().enumerate() {
+            let disk_path = format!("{path}.disks[{i}]");
+            let mut device = None;
+
+            if disk.device.as_os_str().is_empty() {
+                validator.error(
+                    format!("{disk_path}.device"),
+                    "the device must not be empty",
+                );
+            } else {
+                let device_path = format!("{disk_path}.device");
+                device = validate_device_exists(&device_path, &disk.device, &scanner, validator);
+
+                if !disks.insert(&disk.device) {
+                    validator.error(
+                        device_path,
+                        format!(
+                            "the disk {} is partitioned more than once",
+                            disk.device.display()
+                        ),
+                    );
+                }
+            }
+            validate_layout(
+                &format!("{disk_path}.layout"),
+                device.as_ref(),
+                disk.table,
+                &disk.layout,
+                validator,
+            );
+        }
+        let targets = self.targets(path);
+        // existing filesystems can only be checked if the devices can be listed
+        let existing = scanner.scan().ok();
+        validate_targets(path, &targets, existing.as_deref(), validator);
+
+        if self.efi_partition
+            && matches!(self.partitions, Partitions::Layout(_))
+            && !targets.iter().any(|t| t.is_esp)
+        {
+            validator.error(
+                format!("{path}.partitions.Layout"),
+                "efi_partition requires a partition mounted at /boot/efi or /efi, a FAT partition mounted at /boot or a partition with the EFI system partition type",
+            );
+        }
+        self.validate_raid_and_lvm(path, &targets, validator);
+    }
+}
+
+impl PartitionsConfig {
+    fn validate_raid_and_lvm(&self, path: &str, targets: &[Target], validator: &mut Validator) {
+        let mut names = HashSet::new();
+        // the members of arrays and volume groups with the config path of the array or group
+        let mut members = Vec::new();
+
+        for (i, array) in self.raid_arrays.iter().enumerate() {
+            let array_path = format!("{path}.raid_arrays[{i}]");
+
+            if !is_valid_device_name(&array.name) {
+                validator.error(format!("{array_path}.name"), "invalid RAID array name");
+            } else if !names.insert(&array.name) {
+                validator.error(
+                    format!("{array_path}.name"),
+                    format!("the RAID array {} is defined more than once", array.name),
+                );
+            }
+            if array.devices.len() < array.level.min_devices() {
+                validator.error(
+                    format!("{array_path}.devices"),
+                    format!(
+                        "{:?} needs at least {} devices",
+                        array.level,
+                        array.level.min_devices()
+                    ),
+                );
+            }
+            members.extend(array.devices.iter().map(|d| (array_path.clone(), d)));
+        }
+        let mut names = HashSet::new();
+
+        for (i, group) in self.volume_groups.iter().enumerate() {
+            let group_path = format!("{path}.volume_groups[{i}]");
+
+            if !is_valid_device_name(&group.name) {
+                validator.error(format!("{group_path}.name"), "invalid volume group name");
+            } else if !names.insert(&group.name) {
+                validator.error(
+                    format!("{group_path}.name"),
+                    format!("the volume group {} is defined more than once", group.name),
+                );
+            }
+            if group.physical_volumes.is_empty() {
+                validator.error(
+                    format!("{group_path}.physical_volumes"),
+                    "a volume group needs at least one physical volume",
+                );
+            }
+            members.extend(
+                group
+                    .physical_volumes
+                    .iter()
+                    .map(|d| (group_path.clone(), d)),
+            );
+            let mut volume_names = HashSet::new();
+            let mut has_rest = false;
+
+            for (j, volume) in group.logical_volumes.iter().enumerate() {
+                let volume_path = format!("{group_path}.logical_volumes[{j}]");
+
+                if !is_valid_device_name(&volume.name) {
+                    validator.error(format!("{volume_path}.name"), "invalid logical volume name");
+                } else if !volume_names.insert(&volume.name) {
+                    validator.error(
+                        format!("{volume_path}.name"),
+                        format!(
+                            "the logical volume {} is defined more than once",
+                            volume.name
+                        ),
+                    );
+                }
+                validate_size(&volume_path, volume.size, &mut has_rest, validator);
+            }
+        }
+
+        // devices that exist after partitioning. Only complete if all disks are repartitioned
+        let mut known = targets
+            .iter()
+            .map(|t| t.device.clone())
+            .collect::<HashSet<_>>();
+        let mut used = HashMap::new();
+
+        for target in targets {
+            if let Some(name) = target.volume.mapper_name(&target.device) {
+                known.insert(Path::new("/dev/mapper").join(name));
+            }
+        }
+        for (member_path, member) in members {
+            if !member.is_absolute() {
+                validator.error(
+                    &member_path,
+                    format!("the device {} must be absolute", member.display()),
+                );
+                continue;
+            }
+            if matches!(self.partitions, Partitions::Layout(_)) && !known.contains(member) {
+                validator.error(
+                    &member_path,
+                    format!("the device {} is not defined", member.display()),
+                );
+            }
+            if let Some(other) = used.insert(member, member_path.clone()) {
+                validator.error(
+                    &member_path,
+                    format!("the device {} is already used by {other}", member.display()),
+                );
+            }
+            let target = targets.iter().find(|t| {
+                let mapper = t
+                    .volume
+                    .mapper_name(&t.device)
+                    .map(|n| Path::new("/dev/mapper").join(n));
+
+                (&t.device == member && t.volume.is_used())
+                    || (mapper.as_ref() == Some(member)
+                        && (t.volume.filesystem.is_some() || t.volume.mountpoint.is_some()))
+            });
+            if let Some(target) = target {
+                validator.error(
+                    &member_path,
+                    format!(
+                        "the device {} is already used by {}",
+                        member.display(),
+                        target.path
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// Reports an error if the disk doesn't exist and returns it otherwise
+fn validate_device_exists(
+    path: &str,
+    device: &Path,
+    scanner: &DeviceScanner,
+    validator: &mut Validator,
+) -> Option<BlockDevice> {
+    match scanner.find(device) {
+        Ok(Some(disk)) => return Some(disk),
+        Ok(None) => validator.error(path, format!("the disk {} doesn't exist", device.display())),
+        Err(e) => validator.error(path, format!("the disks couldn't be listed: {e}")),
+    }
+
+    None
+}
+
+fn layout_targets<'a>(
+    path: &'a str,
+    device: &'a Path,
+    layout: &'a [PartitionSpec],
+) -> impl Iterator<Item = Target> + 'a {
+    layout.iter().enumerate().map(move |(i, spec)| Target {
+        path: format!("{path}[{i}]"),
+        device: partition_device(device, i as u32 + 1),
+        volume: spec.volume.clone(),
+        is_esp: spec.is_esp(),
+        keeps_filesystem: false,
+    })
+}
+
+fn validate_layout(
+    path: &str,
+    device: Option<&BlockDevice>,
+    table: PartitionTable,
+    layout: &[PartitionSpec],
+    validator: &mut Validator,
+) {
+    let mut has_rest = false;
+
+    if table == PartitionTable::Mbr && layout.len() > 4 {
+        validator.error(path, "MBR partition tables support at most 4 partitions");
+    }
+    for (i, spec) in layout.iter().enumerate() {
+        let spec_path = format!("{path}[{i}]");
+        validate_size(&spec_path, spec.size, &mut has_rest, validator);
+
+        if spec.is_bios_boot() && spec.volume.is_used() {
+            validator.error(
+                &spec_path,
+                "the BIOS boot partition must not be formatted, mounted or encrypted",
+            );
+        }
+        if let Some(guid) = &spec.type_guid {
+            if table == PartitionTable::Mbr {
+                validator.error(
+                    format!("{spec_path}.type_guid"),
+                    "partition type GUIDs are only supported on GPT",
+                );
+            } else if !is_valid_guid(guid) {
+                validator.error(
+                    format!("{spec_path}.type_guid"),
+                    format!("{guid} is not a valid GUID"),
+                );
+            }
+        }
+    }
+    // a missing device is already reported for the device itself
+    let Some(device) = device else {
+        return;
+    };
+    if device.size == 0 {
+        validator.error(
+            path,
+            format!(
+                "the size of {} couldn't be read to check that the layout fits",
+                device.path.display()
+            ),
+        );
+        return;
+    }
+    // the IDs don't matter for the positions of the partitions
+    if let Err(e) = layout_partitions("", &device.path, table, layout, device.size) {
+        validator.error(path, e);
+    }
+}
+
+fn validate_size(path: &str, size: PartitionSize, has_rest: &mut bool, validator: &mut Validator) {
+    match size {
+        PartitionSize::Bytes(0) => {
+            validator.error(format!("{path}.size"), "the size must not be zero")
+        }
+        PartitionSize::Rest if *has_rest => validator.error(
+            format!("{path}.size"),
+            "only one volume can use the rest of the space",
+        ),
+        PartitionSize::Rest => *has_rest = true,
+        _ => {}
+    }
+}
+
+/// Checks the usage of all devices that get formatted, mounted or encrypted
+fn validate_targets(
+    path: &str,
+    targets: &[Target],
+    existing: Option<&[BlockDevice]>,
+    validator: &mut Validator,
+) {
+    let mut mountpoints = HashSet::new();
+    let mut mapper_names = HashSet::new();
+
+    for target in targets {
+        let volume = &target.volume;
+        // the type of a kept filesystem is unknown if the devices couldn't be listed
+        let kept_filesystem = match existing {
+            Some(devices) if target.keeps_filesystem => {
+                Some(find_partition(devices, &target.device).and_then(|p| p.filesystem.as_deref()))
+            }
+            _ => None,
+        };
+
+        if volume.is_swap() && volume.mountpoint.is_some() {
+            validator.error(
+                format!("{}.mountpoint", target.path),
+                "swap partitions can't be mounted",
+            );
+        } else if volume.mountpoint.is_some() && volume.filesystem.is_none() {
+            match kept_filesystem {
+                Some(None) => validator.error(
+                    format!("{}.filesystem", target.path),
+                    format!(
+                        "{} has no filesystem to keep. Set the filesystem to format it",
+                        target.device.display()
+                    ),
+                ),
+                None if !target.keeps_filesystem => validator.error(
+                    format!("{}.filesystem", target.path),
+                    "mounted partitions need a filesystem",
+                ),
+                _ => {}
+            }
+        }
+        if let Some(btrfs) = &volume.btrfs {
+            validator.validate(&format!("{}.btrfs", target.path), btrfs);
+            let is_btrfs = match &volume.filesystem {
+                Some(filesystem) => *filesystem == FileSystem::BTRFS,
+                None => kept_filesystem.map_or(target.keeps_filesystem, |f| f == Some("btrfs")),
+            };
+
+            if !is_btrfs {
+                validator.error(
+                    format!("{}.btrfs", target.path),
+                    "btrfs options require the BTRFS filesystem",
+                );
+            }
+        }
+        for mountpoint in volume.mountpoints() {
+            if !mountpoint.is_absolute() {
+                validator.error(
+                    &target.path,
+                    format!("the mountpoint {} must be absolute", mountpoint.display()),
+                );
+            } else if !mountpoints.insert(mountpoint) {
+                validator.error(
+                    &target.path,
+                    format!(
+                        "the mountpoint {} is used more than once",
+                        mountpoint.display()
+                    ),
+                );
+            }
+        }
+        if let Some(encryption) = &volume.encryption {
+            let encryption_path = format!("{}.encryption", target.path);
+            validator.validate(&encryption_path, encryption);
+
+            if target.is_esp {
+                validator.error(
+                    &encryption_path,
+                    "the EFI system partition can't be encrypted",
+                );
+            }
+            let name = encryption.mapper_name(&target.device, volume.is_mounted_at("/"));
+
+            if !mapper_names.insert(name.clone()) {
+                validator.error(
+                    &encryption_path,
+                    format!("the mapper name {name} is used more than once"),
+                );
+            }
+        }
+    }
+    if !mountpoints.contains(Path::new("/")) {
+        validator.error(format!("{path}.partitions"), "no partition is mounted at /");
+    }
+}
+
+/// Returns if the name can be used for a RAID array, volume group or logical volume
+fn is_valid_device_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '+'))
 }
@@ -0,0 +1,289 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use embed_nu::rusty_value::{
+    Float, HashablePrimitive, HashableValue, Integer, Primitive, RustyValue, Value,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{AppError, AppResult},
+    locales::closest_match,
+    validation::Validator,
+};
+
+use super::TaskFiles;
+
+/// Arbitrary arguments passed to a custom task
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(transparent)]
+pub struct CustomArgs(pub serde_json::Value);
+
+impl RustyValue for CustomArgs {
+    fn into_rusty_value(self) -> Value {
+        json_into_rusty_value(self.0)
+    }
+}
+
+pub(crate) fn json_into_rusty_value(value: serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::None,
+        serde_json::Value::Bool(b) => Value::Primitive(Primitive::Bool(b)),
+        serde_json::Value::Number(n) => Value::Primitive(if let Some(i) = n.as_i64() {
+            Primitive::Integer(Integer::I64(i))
+        } else if let Some(u) = n.as_u64() {
+            Primitive::Integer(Integer::U64(u))
+        } else {
+            Primitive::Float(Float::F64(n.as_f64().unwrap_or_default()))
+        }),
+        serde_json::Value::String(s) => Value::Primitive(Primitive::String(s)),
+        serde_json::Value::Array(vals) => {
+            Value::List(vals.into_iter().map(json_into_rusty_value).collect())
+        }
+        serde_json::Value::Object(map) => Value::Map(
+            map.into_iter()
+                .map(|(k, v)| {
+                    (
+                        HashableValue::Primitive(HashablePrimitive::String(k)),
+                        json_into_rusty_value(v),
+                    )
+                })
+                .collect(),
+        ),
+    }
+}
+
+/// The manifest of a user defined task stored as `tasks/<name>.json`
+/// in the config directory. The task executes the script `scripts/<name>.nu`
+/// and the hooks `hooks/<name>.pre.nu` and `hooks/<name>.post.nu`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TaskManifest {
+    #[serde(skip)]
+    pub name: String,
+    /// The tasks that need to be executed before this one
+    #[serde(default)]
+    pub after: Vec<String>,
+    /// The tasks that need to be executed after this one
+    #[serde(default)]
+    pub before: Vec<String>,
+    /// A JSON pointer into the config (e.g. `/enable_zramd`).
+    /// The task is skipped if the value is missing, `null`, `false` or empty
+    #[serde(default)]
+    pub condition: Option<String>,
+    /// Gives the task and its hooks access to the passwords in `TRM_CONFIG`.
+    /// All other tasks only see them redacted
+    #[serde(default)]
+    pub secrets: bool,
+    /// The arguments the task accepts. The values are taken from
+    /// the `custom_tasks.<name>` section of the config
+    #[serde(default)]
+    pub arguments: BTreeMap<String, ArgumentSpec>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ArgumentSpec {
+    #[serde(default, rename = "type")]
+    pub kind: ArgumentType,
+    #[serde(default)]
+    pub required: bool,
+    pub default: Option<serde_json::Value>,
+    pub description: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArgumentType {
+    String,
+    Integer,
+    Number,
+    Boolean,
+    List,
+    Record,
+    #[default]
+    Any,
+}
+
+impl ArgumentType {
+    fn matches(&self, value: &serde_json::Value) -> bool {
+        match self {
+            ArgumentType::String => value.is_string(),
+            ArgumentType::Integer => value.is_i64() || value.is_u64(),
+            ArgumentType::Number => value.is_number(),
+            ArgumentType::Boolean => value.is_boolean(),
+            ArgumentType::List => value.is_array(),
+            ArgumentType::Record => value.is_object(),
+            ArgumentType::Any => true,
+        }
+    }
+}
+
+impl TaskManifest {
+    /// Loads the manifest from the given file.
+    /// The name of the task is the file name without the extension
+    pub fn load(path: &Path) -> AppResult<Self> {
+        let contents = fs::read_to_string(path)?;
+        let mut manifest: TaskManifest = serde_json::from_str(&contents)
+            .map_err(|e| AppError::InvalidManifest(path.to_owned(), e))?;
+        manifest.name = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        Ok(manifest)
+    }
+
+    /// Loads all manifests in the given directory sorted by their name
+    pub fn load_all(dir: &Path) -> AppResult<Vec<Self>> {
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut paths = fs::read_dir(dir)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<Vec<PathBuf>, _>>()?;
+        paths.retain(|p| p.extension().map(|e| e == "json").unwrap_or(false));
+        paths.sort();
+
+        paths.iter().map(|p| Self::load(p)).collect()
+    }
+
+    pub fn files(&self) -> TaskFiles {
+        TaskFiles {
+            script: format!("{}.nu", self.name),
+            pre_hook: format!("{}.pre.nu", self.name),
+            post_hook: format!("{}.post.nu", self.name),
+            dependencies: self.after.clone(),
+            dependents: self.before.clone(),
+            secrets: self.secrets,
+        }
+    }
+
+    /// Returns if the condition of the task is met for the given config
+    pub fn condition_met(&self, config: &serde_json::Value) -> bool {
+        let pointer = match &self.condition {
+            Some(pointer) => pointer,
+            None => return true,
+        };
+
+        match config.pointer(pointer) {
+            None | Some(serde_json::Value::Null) | Some(serde_json::Value::Bool(false)) => false,
+            Some(serde_json::Value::String(s)) => !s.is_empty(),
+            Some(serde_json::Value::Array(a)) => !a.is_empty(),
+            Some(serde_json::Value::Object(o)) => !o.is_empty(),
+            Some(_) => true,
+        }
+    }
+
+    /// Checks the given arguments against the argument specification
+    /// and fills in default values
+    pub fn resolve_args(&self, args: Option<CustomArgs>) -> AppResult<CustomArgs> {
+        let args = args.map(|a| a.0).unwrap_or_default();
+
+        if self.arguments.is_empty() {
+            return Ok(CustomArgs(args));
+        }
+        let mut values = match args {
+            serde_json::Value::Object(map) => map,
+            serde_json::Value::Null => serde_json::Map::new(),
+            _ => return Err(self.invalid_args("the arguments need to be a record")),
+        };
+
+        if let Some(unknown) = values.keys().find(|k| !self.arguments.contains_key(*k)) {
+            return Err(self.invalid_args(format!("unknown argument `{unknown}`")));
+        }
+
+        for (name, spec) in &self.arguments {
+            match values.get(name) {
+                Some(value) if !spec.kind.matches(value) => {
+                    return Err(self.invalid_args(format!(
+                        "argument `{name}` needs to be of type {:?}",
+                        spec.kind
+                    )))
+                }
+                Some(_) => {}
+                None => {
+                    if let Some(default) = &spec.default {
+                        values.insert(name.to_owned(), default.to_owned());
+                    } else if spec.required {
+                        return Err(self.invalid_args(format!("missing argument `{name}`")));
+                    }
+                }
+            }
+        }
+
+        Ok(CustomArgs(serde_json::Value::Object(values)))
+    }
+
+    fn invalid_args<S: ToString>(&self, message: S) -> AppError {
+        AppError::InvalidTaskArgs(self.name.to_owned(), message.to_string())
+    }
+}
+
+/// A user defined task together with its resolved arguments
+#[derive(Clone, Debug)]
+pub struct CustomTask {
+    pub manifest: TaskManifest,
+    pub args: CustomArgs,
+}
+
+/// Reports the arguments in `custom_tasks` that don't belong to any of the given tasks
+pub(crate) fn validate_custom_args(
+    path: &str,
+    args: &HashMap<String, CustomArgs>,
+    manifests: &[TaskManifest],
+    validator: &mut Validator,
+) {
+    let names = manifests
+        .iter()
+        .map(|m| m.name.as_str())
+        .collect::<Vec<_>>();
+    let mut unknown = args
+        .keys()
+        .filter(|k| !names.contains(&k.as_str()))
+        .collect::<Vec<_>>();
+    unknown.sort();
+
+    for name in unknown {
+        let message = match closest_match(name, &names) {
+            Some(suggestion) => {
+                format!("there is no custom task named `{name}`. Did you mean `{suggestion}`?")
+            }
+            None => format!("there is no custom task named `{name}`"),
+        };
+        validator.error(format!("{path}.{name}"), message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(name: &str) -> TaskManifest {
+        let mut manifest: TaskManifest = serde_json::from_str("{}").unwrap();
+        manifest.name = name.into();
+        manifest
+    }
+
+    #[test]
+    fn reports_args_without_a_task() {
+        let args = ["enable-ssh", "setup_mirrors"]
+            .into_iter()
+            .map(|n| (n.to_owned(), CustomArgs::default()))
+            .collect();
+        let mut validator = Validator::default();
+        validate_custom_args(
+            "$.custom_tasks",
+            &args,
+            &[manifest("enable-ssh"), manifest("setup-mirrors")],
+            &mut validator,
+        );
+        let errors = validator.into_errors();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, "$.custom_tasks.setup_mirrors");
+        assert!(errors[0].message.contains("Did you mean `setup-mirrors`?"));
+    }
+}
@@ -1,8 +1,17 @@
+use embed_nu::rusty_value::*;
+use serde::{Deserialize, Serialize};
+
 use crate::script;
 
 script!(InstallBaseScript {
     file = "install-base"
     args = InstallBaseArgs
+    after = ["create-partitions"]
 });
 
-type InstallBaseArgs = ();
+/// The arguments passed to the install-base script
+#[derive(Clone, Debug, Default, Deserialize, Serialize, RustyValue)]
+pub struct InstallBaseArgs {
+    /// Packages left out of the base installation
+    pub exclude: Vec<String>,
+}
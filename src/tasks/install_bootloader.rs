@@ -1,23 +1,303 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use embed_nu::rusty_value::*;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
-use crate::script;
+use crate::{
+    partitioning::RootDevice,
+    script,
+    tasks::{InstalledKernel, KernelConfig, PartitionTable, Partitions, PartitionsConfig},
+    validation::{Validate, Validator},
+};
 
 script!(InstallBootloaderScript {
     file = "install-bootloader"
-    args = BootloaderConfig
+    args = InstallBootloaderArgs
+    after = ["install-kernels"]
 });
 
-#[derive(Clone, Debug, Deserialize, RustyValue)]
+/// The arguments passed to the install-bootloader script
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue)]
+pub struct InstallBootloaderArgs {
+    pub config: BootloaderConfig,
+    /// The device of the root filesystem. Unknown for automatic partitioning
+    pub root: Option<RootDevice>,
+    /// The kernel command line shared by all entries
+    pub kernel_params: Vec<String>,
+    /// One entry per installed kernel for loaders that need explicit entries
+    pub entries: Vec<BootEntry>,
+}
+
+/// A boot menu entry for one kernel. Paths are relative to the root of the installed system
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue)]
+pub struct BootEntry {
+    pub id: String,
+    pub title: String,
+    /// The name of the kernel package
+    pub kernel: String,
+    pub linux: PathBuf,
+    pub initrd: Vec<PathBuf>,
+    /// The kernel command line
+    pub options: Vec<String>,
+    /// Whether the entry is booted by default
+    pub default: bool,
+    /// Whether the entry boots the fallback initramfs
+    pub fallback: bool,
+}
+
+impl InstallBootloaderArgs {
+    pub fn new(
+        config: BootloaderConfig,
+        root: Option<RootDevice>,
+        kernel_params: Vec<String>,
+        kernels: &[InstalledKernel],
+    ) -> Self {
+        let entries = kernels
+            .iter()
+            .flat_map(|kernel| {
+                let fallback = kernel.fallback_initramfs.as_ref().map(|image| BootEntry {
+                    id: format!("{}-fallback", kernel.name),
+                    title: format!("Linux ({}, fallback initramfs)", kernel.name),
+                    kernel: kernel.name.clone(),
+                    linux: kernel.linux.clone(),
+                    initrd: vec![image.clone()],
+                    options: kernel.cmdline.clone(),
+                    default: false,
+                    fallback: true,
+                });
+                std::iter::once(BootEntry {
+                    id: kernel.name.clone(),
+                    title: format!("Linux ({})", kernel.name),
+                    kernel: kernel.name.clone(),
+                    linux: kernel.linux.clone(),
+                    initrd: vec![kernel.initramfs.clone()],
+                    options: kernel.cmdline.clone(),
+                    default: kernel.boot_default,
+                    fallback: false,
+                })
+                .chain(fallback)
+            })
+            .collect();
+
+        Self {
+            config,
+            root,
+            kernel_params,
+            entries,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
 pub struct BootloaderConfig {
     pub preset: BootloaderPreset,
+    /// The mountpoint of the EFI system partition for EFI loaders
+    /// or the disk the boot code is written to for BIOS loaders
     pub location: PathBuf,
+    /// The time in seconds the menu is shown before booting the default entry
+    pub timeout: Option<u32>,
+    /// The kernel booted by default. Defaults to the kernel with `boot_default`
+    /// or the default kernel
+    pub default_entry: Option<String>,
+    /// Options for the GRUB presets
+    pub grub: Option<GrubOptions>,
+    pub systemd_boot: Option<SystemdBootOptions>,
+    pub limine: Option<LimineOptions>,
+    pub refind: Option<RefindOptions>,
 }
 
-#[derive(Clone, Debug, Deserialize, RustyValue)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, RustyValue, JsonSchema)]
 pub enum BootloaderPreset {
     GrubEfi,
+    /// GRUB for BIOS systems on a disk with an MBR partition table
     Legacy,
+    /// GRUB for BIOS systems on a disk with a GPT partition table.
+    /// Requires a BIOS boot partition
+    GrubBios,
+    SystemdBoot,
+    Limine,
+    Refind,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct GrubOptions {
+    /// Adds entries for other operating systems found by os-prober
+    #[serde(default)]
+    pub os_prober: bool,
+    /// Installs to the fallback path `EFI/BOOT/BOOTX64.EFI` instead of registering an NVRAM entry
+    #[serde(default)]
+    pub removable: bool,
+    /// The path of the theme.txt of a GRUB theme
+    pub theme: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct SystemdBootOptions {
+    /// Allows editing the kernel command line at boot
+    #[serde(default)]
+    pub editor: bool,
+    pub console_mode: Option<ConsoleMode>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub enum ConsoleMode {
+    Auto,
+    Keep,
+    Max,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct LimineOptions {
+    /// The branding shown at the top of the menu
+    pub branding: Option<String>,
+    /// The path of the wallpaper image on the installed system
+    pub wallpaper: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct RefindOptions {
+    /// Uses the graphical mode for the menu
+    #[serde(default)]
+    pub use_graphics: bool,
+    /// The name of a theme directory inside the rEFInd directory on the ESP
+    pub theme: Option<String>,
+}
+
+impl BootloaderPreset {
+    pub fn is_efi(&self) -> bool {
+        !matches!(self, BootloaderPreset::Legacy | BootloaderPreset::GrubBios)
+    }
+
+    fn is_grub(&self) -> bool {
+        matches!(
+            self,
+            BootloaderPreset::GrubEfi | BootloaderPreset::Legacy | BootloaderPreset::GrubBios
+        )
+    }
+}
+
+impl BootloaderConfig {
+    /// Checks that the bootloader can be installed with the partitions and kernels
+    pub(crate) fn validate_setup(
+        &self,
+        path: &str,
+        partitions: &PartitionsConfig,
+        kernels: &KernelConfig,
+        validator: &mut Validator,
+    ) {
+        let preset = self.preset;
+
+        if preset.is_efi() {
+            if !partitions.efi_partition {
+                validator.error(
+                    format!("{path}.preset"),
+                    format!("{preset:?} requires partitions.efi_partition to be enabled"),
+                );
+            }
+            // the ESP is only known if the partitions are configured
+            if let Some(esp) = partitions.esp_mountpoint() {
+                if self.location != esp {
+                    validator.error(
+                        format!("{path}.location"),
+                        format!(
+                            "the location must be the mountpoint of the EFI system partition {}",
+                            esp.display()
+                        ),
+                    );
+                }
+                let needs_boot_esp = matches!(
+                    preset,
+                    BootloaderPreset::SystemdBoot | BootloaderPreset::Limine
+                );
+
+                if needs_boot_esp && esp != Path::new("/boot") {
+                    validator.error(
+                        format!("{path}.preset"),
+                        format!("{preset:?} loads the kernels from the EFI system partition which therefore has to be mounted at /boot"),
+                    );
+                }
+            }
+        }
+        if let Partitions::Layout(_) = partitions.partitions {
+            match (preset, partitions.table) {
+                (BootloaderPreset::Legacy, PartitionTable::Gpt) => validator.error(
+                    format!("{path}.preset"),
+                    "Legacy requires an MBR partition table. Use GrubBios for GPT",
+                ),
+                (BootloaderPreset::GrubBios, PartitionTable::Mbr) => validator.error(
+                    format!("{path}.preset"),
+                    "GrubBios requires a GPT partition table. Use Legacy for MBR",
+                ),
+                (BootloaderPreset::GrubBios, PartitionTable::Gpt)
+                    if !partitions.has_bios_boot_partition() =>
+                {
+                    validator.error(
+                        format!("{path}.preset"),
+                        "GrubBios requires a BIOS boot partition on the device",
+                    )
+                }
+                _ => {}
+            }
+        }
+        if let Some(entry) = &self.default_entry {
+            if !kernels.kernels().any(|k| &k.name == entry) {
+                validator.error(
+                    format!("{path}.default_entry"),
+                    format!("{entry} is not one of the installed kernels"),
+                );
+            }
+            if let Some(kernel) = kernels
+                .kernels()
+                .find(|k| k.boot_default && &k.name != entry)
+            {
+                validator.error(
+                    format!("{path}.default_entry"),
+                    format!(
+                        "{entry} conflicts with boot_default of the kernel {}",
+                        kernel.name
+                    ),
+                );
+            }
+        }
+    }
+}
+
+impl Validate for BootloaderConfig {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        if self.location.as_os_str().is_empty() {
+            validator.error(format!("{path}.location"), "the location must not be empty");
+        } else if !self.location.is_absolute() {
+            validator.error(format!("{path}.location"), "the location must be absolute");
+        }
+        let options = [
+            ("grub", self.grub.is_some() && !self.preset.is_grub()),
+            (
+                "systemd_boot",
+                self.systemd_boot.is_some() && self.preset != BootloaderPreset::SystemdBoot,
+            ),
+            (
+                "limine",
+                self.limine.is_some() && self.preset != BootloaderPreset::Limine,
+            ),
+            (
+                "refind",
+                self.refind.is_some() && self.preset != BootloaderPreset::Refind,
+            ),
+        ];
+        for (name, _) in options.iter().filter(|(_, unused)| *unused) {
+            validator.error(
+                format!("{path}.{name}"),
+                format!("the options are not used by the {:?} preset", self.preset),
+            );
+        }
+        if let Some(grub) = &self.grub {
+            if grub.removable && !self.preset.is_efi() {
+                validator.error(
+                    format!("{path}.grub.removable"),
+                    "only EFI installations can be removable",
+                );
+            }
+        }
+    }
 }
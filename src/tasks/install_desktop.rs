@@ -1,14 +1,16 @@
 use embed_nu::rusty_value::*;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 use crate::script;
 
 script!(InstallDesktopScript {
     file = "install-desktop"
     args = DesktopConfig
+    after = ["install-base"]
 });
 
-#[derive(Clone, Debug, Deserialize, RustyValue)]
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
 pub enum DesktopConfig {
     Onyx,
     KdePlasma,
@@ -1,8 +1,260 @@
-use crate::script;
+use std::{collections::HashSet, path::PathBuf};
+
+use embed_nu::rusty_value::*;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    script,
+    validation::{Validate, Validator},
+};
+
+/// The file extensions of pacman packages
+const PACKAGE_EXTENSIONS: &[&str] = &[".pkg.tar.zst", ".pkg.tar.xz", ".pkg.tar.gz", ".pkg.tar"];
+
+/// The URL schemes pacman can download packages from
+const SERVER_SCHEMES: &[&str] = &["http://", "https://", "ftp://", "file://"];
 
 script!(InstallExtraPackagesScript {
     file = "install-extra-packages"
     args = ExtraPackages
+    after = ["install-base", "setup-users"]
 });
 
-pub type ExtraPackages = Vec<String>;
+/// Packages installed in addition to the desktop. Can be given as a list of package names
+#[derive(Clone, Debug, Default, Deserialize, Serialize, RustyValue)]
+#[serde(from = "ExtraPackagesEntry")]
+pub struct ExtraPackages {
+    /// Packages from the official and the additional repositories
+    pub packages: Vec<String>,
+    /// Repositories added to the pacman.conf of the installed system
+    pub repositories: Vec<Repository>,
+    pub aur: Option<AurConfig>,
+    /// Package files on the installation medium
+    pub local: Vec<PathBuf>,
+    /// Packages left out of the base installation
+    pub exclude: Vec<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum ExtraPackagesEntry {
+    Packages(Vec<String>),
+    Options {
+        #[serde(default)]
+        packages: Vec<String>,
+        #[serde(default)]
+        repositories: Vec<Repository>,
+        aur: Option<AurConfig>,
+        #[serde(default)]
+        local: Vec<PathBuf>,
+        #[serde(default)]
+        exclude: Vec<String>,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct Repository {
+    /// The name of the repository section in the pacman.conf
+    pub name: String,
+    /// The server URL. May contain the `$repo` and `$arch` variables
+    pub server: String,
+    /// The fingerprint of the key the packages are signed with.
+    /// The key is imported and locally signed before the repository is used
+    pub key: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct AurConfig {
+    #[serde(default)]
+    pub helper: AurHelper,
+    pub packages: Vec<String>,
+    /// The user building the packages since makepkg refuses to run as root.
+    /// Defaults to the first user
+    pub user: Option<String>,
+}
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, RustyValue, JsonSchema,
+)]
+pub enum AurHelper {
+    #[default]
+    Paru,
+    Yay,
+}
+
+impl From<ExtraPackagesEntry> for ExtraPackages {
+    fn from(entry: ExtraPackagesEntry) -> Self {
+        match entry {
+            ExtraPackagesEntry::Packages(packages) => Self {
+                packages,
+                ..Default::default()
+            },
+            ExtraPackagesEntry::Options {
+                packages,
+                repositories,
+                aur,
+                local,
+                exclude,
+            } => Self {
+                packages,
+                repositories,
+                aur,
+                local,
+                exclude,
+            },
+        }
+    }
+}
+
+impl JsonSchema for ExtraPackages {
+    fn schema_name() -> String {
+        "ExtraPackages".into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        ExtraPackagesEntry::json_schema(gen)
+    }
+}
+
+impl ExtraPackages {
+    /// Checks that the AUR packages can be built by one of the configured users
+    pub(crate) fn validate_users(&self, path: &str, users: &[String], validator: &mut Validator) {
+        let Some(aur) = &self.aur else {
+            return;
+        };
+        match &aur.user {
+            Some(user) if !users.contains(user) => validator.error(
+                format!("{path}.aur.user"),
+                format!("{user} is not one of the configured users"),
+            ),
+            None if users.is_empty() => validator.error(
+                format!("{path}.aur"),
+                "building AUR packages requires at least one user",
+            ),
+            _ => {}
+        }
+    }
+}
+
+impl Validate for ExtraPackages {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        let aur_packages = self.aur.iter().flat_map(|aur| &aur.packages);
+        let mut packages = HashSet::new();
+
+        for (package_path, package) in indexed(&format!("{path}.packages"), &self.packages)
+            .chain(indexed(&format!("{path}.aur.packages"), aur_packages))
+        {
+            if !is_valid_package_name(package) {
+                validator.error(
+                    package_path,
+                    format!("`{package}` is not a valid package name"),
+                );
+            } else if !packages.insert(package) {
+                validator.error(
+                    package_path,
+                    format!("the package {package} is listed more than once"),
+                );
+            } else if self.exclude.contains(package) {
+                validator.error(
+                    package_path,
+                    format!("the package {package} is also excluded"),
+                );
+            }
+        }
+        for (exclude_path, package) in indexed(&format!("{path}.exclude"), &self.exclude) {
+            if !is_valid_package_name(package) {
+                validator.error(
+                    exclude_path,
+                    format!("`{package}` is not a valid package name"),
+                );
+            }
+        }
+        let mut names = HashSet::new();
+
+        for (i, repository) in self.repositories.iter().enumerate() {
+            let repository_path = format!("{path}.repositories[{i}]");
+            validator.validate(&repository_path, repository);
+
+            if !names.insert(&repository.name) {
+                validator.error(
+                    format!("{repository_path}.name"),
+                    format!(
+                        "the repository {} is defined more than once",
+                        repository.name
+                    ),
+                );
+            }
+        }
+        for (i, file) in self.local.iter().enumerate() {
+            let name = file.to_string_lossy();
+
+            if !file.is_absolute() {
+                validator.error(
+                    format!("{path}.local[{i}]"),
+                    "the package file must be an absolute path",
+                );
+            } else if !PACKAGE_EXTENSIONS.iter().any(|e| name.ends_with(e)) {
+                validator.error(
+                    format!("{path}.local[{i}]"),
+                    format!("{name} is not a package file"),
+                );
+            }
+        }
+    }
+}
+
+impl Validate for Repository {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        let is_valid_name = !self.name.is_empty()
+            && self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+        // `options` is the section of the general pacman settings
+        if !is_valid_name || self.name == "options" {
+            validator.error(
+                format!("{path}.name"),
+                format!("`{}` is not a valid repository name", self.name),
+            );
+        }
+        if !SERVER_SCHEMES.iter().any(|s| self.server.starts_with(s)) {
+            validator.error(
+                format!("{path}.server"),
+                format!(
+                    "the server must be a URL starting with one of {}",
+                    SERVER_SCHEMES.join(", ")
+                ),
+            );
+        }
+        if let Some(key) = &self.key {
+            if key.len() != 40 || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+                validator.error(
+                    format!("{path}.key"),
+                    "the key must be a fingerprint of 40 hex digits",
+                );
+            }
+        }
+    }
+}
+
+fn indexed<'a, I: IntoIterator<Item = &'a String>>(
+    path: &'a str,
+    values: I,
+) -> impl Iterator<Item = (String, &'a String)> {
+    values
+        .into_iter()
+        .enumerate()
+        .map(move |(i, value)| (format!("{path}[{i}]"), value))
+}
+
+/// Returns if the name is allowed by pacman. Names consist of lowercase letters,
+/// digits and `@._+-` and must not start with a hyphen or dot
+fn is_valid_package_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['-', '.'])
+        && name.chars().all(|c| {
+            c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '@' | '.' | '_' | '+' | '-')
+        })
+}
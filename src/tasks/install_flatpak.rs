@@ -1,8 +1,284 @@
-use crate::script;
+use std::collections::{HashMap, HashSet};
+
+use embed_nu::rusty_value::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    script,
+    validation::{Validate, Validator},
+};
+
+const FLATHUB_NAME: &str = "flathub";
+const FLATHUB_URL: &str = "https://dl.flathub.org/repo/flathub.flatpakrepo";
+
+/// The URL schemes flatpak can add remotes from
+const REMOTE_SCHEMES: &[&str] = &["http://", "https://", "file://"];
+
+/// The sockets and devices that can be granted or denied in overrides
+const SOCKETS: &[&str] = &[
+    "x11",
+    "wayland",
+    "fallback-x11",
+    "pulseaudio",
+    "system-bus",
+    "session-bus",
+    "ssh-auth",
+    "pcsc",
+    "cups",
+    "gpg-agent",
+    "inherit-wayland-socket",
+];
+const DEVICES: &[&str] = &["dri", "input", "usb", "kvm", "shm", "all"];
 
 script!(InstallFlatpakScript {
     file = "install-flatpak"
     args = FlatpakConfig
+    after = ["install-base", "setup-users"]
 });
 
-pub type FlatpakConfig = ();
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct FlatpakConfig {
+    /// The remotes added before installing the applications. Defaults to Flathub
+    #[serde(default = "default_remotes")]
+    pub remotes: Vec<FlatpakRemote>,
+    /// The applications installed from the remotes
+    #[serde(default)]
+    pub apps: Vec<FlatpakApp>,
+    /// Permission overrides applied with `flatpak override`
+    #[serde(default)]
+    pub overrides: Vec<FlatpakOverride>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct FlatpakRemote {
+    pub name: String,
+    /// The URL of the repository or of a `.flatpakrepo` file
+    pub url: String,
+    #[serde(default)]
+    pub scope: FlatpakScope,
+    /// The user the remote is added for with the `User` scope. Defaults to the first user
+    pub user: Option<String>,
+}
+
+/// The installation remotes and the applications installed from them are added to
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, RustyValue, JsonSchema,
+)]
+pub enum FlatpakScope {
+    #[default]
+    System,
+    User,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct FlatpakApp {
+    /// The application ID like `org.mozilla.firefox`
+    /// or a full ref like `app/org.mozilla.firefox/x86_64/stable`
+    #[serde(rename = "ref")]
+    pub app_ref: String,
+    /// The remote the application is installed from. Defaults to the first remote
+    pub remote: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
+pub struct FlatpakOverride {
+    /// The ID of the application. Applies to all applications if not set
+    pub app: Option<String>,
+    /// Filesystem access like `home` or `~/Games:ro`. Prefixed with `!` to deny access
+    #[serde(default)]
+    pub filesystems: Vec<String>,
+    /// Sockets like `wayland`. Prefixed with `!` to deny access
+    #[serde(default)]
+    pub sockets: Vec<String>,
+    /// Devices like `dri`. Prefixed with `!` to deny access
+    #[serde(default)]
+    pub devices: Vec<String>,
+    #[serde(default)]
+    pub environment: HashMap<String, String>,
+}
+
+fn default_remotes() -> Vec<FlatpakRemote> {
+    vec![FlatpakRemote {
+        name: FLATHUB_NAME.into(),
+        url: FLATHUB_URL.into(),
+        scope: FlatpakScope::System,
+        user: None,
+    }]
+}
+
+impl FlatpakConfig {
+    /// Fills in the default users of user remotes and the default remote of applications
+    pub fn resolve(mut self, default_user: Option<&str>) -> Self {
+        for remote in &mut self.remotes {
+            if remote.scope == FlatpakScope::User && remote.user.is_none() {
+                remote.user = default_user.map(String::from);
+            }
+        }
+        let default_remote = self.remotes.first().map(|r| r.name.clone());
+
+        for app in &mut self.apps {
+            if app.remote.is_none() {
+                app.remote = default_remote.clone();
+            }
+        }
+
+        self
+    }
+
+    /// Checks that the users of user remotes are configured
+    pub(crate) fn validate_users(&self, path: &str, users: &[String], validator: &mut Validator) {
+        for (i, remote) in self.remotes.iter().enumerate() {
+            match (&remote.user, remote.scope) {
+                (Some(_), FlatpakScope::System) => validator.error(
+                    format!("{path}.remotes[{i}].user"),
+                    "only remotes with the User scope belong to a user",
+                ),
+                (Some(user), FlatpakScope::User) if !users.contains(user) => validator.error(
+                    format!("{path}.remotes[{i}].user"),
+                    format!("{user} is not one of the configured users"),
+                ),
+                (None, FlatpakScope::User) if users.is_empty() => validator.error(
+                    format!("{path}.remotes[{i}].scope"),
+                    "user remotes require at least one user",
+                ),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Validate for FlatpakConfig {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        let mut names = HashSet::new();
+
+        for (i, remote) in self.remotes.iter().enumerate() {
+            let remote_path = format!("{path}.remotes[{i}]");
+            let is_valid_name = !remote.name.is_empty()
+                && remote
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+
+            if !is_valid_name {
+                validator.error(
+                    format!("{remote_path}.name"),
+                    format!("`{}` is not a valid remote name", remote.name),
+                );
+            } else if !names.insert(&remote.name) {
+                validator.error(
+                    format!("{remote_path}.name"),
+                    format!("the remote {} is defined more than once", remote.name),
+                );
+            }
+            if !REMOTE_SCHEMES.iter().any(|s| remote.url.starts_with(s)) {
+                validator.error(
+                    format!("{remote_path}.url"),
+                    format!(
+                        "the url must start with one of {}",
+                        REMOTE_SCHEMES.join(", ")
+                    ),
+                );
+            }
+        }
+        let mut apps = HashSet::new();
+
+        for (i, app) in self.apps.iter().enumerate() {
+            let app_path = format!("{path}.apps[{i}]");
+
+            if !is_valid_app_ref(&app.app_ref) {
+                validator.error(
+                    format!("{app_path}.ref"),
+                    format!("`{}` is not a valid application ID or ref", app.app_ref),
+                );
+            } else if !apps.insert(&app.app_ref) {
+                validator.error(
+                    format!("{app_path}.ref"),
+                    format!("the application {} is listed more than once", app.app_ref),
+                );
+            }
+            match &app.remote {
+                Some(remote) if !self.remotes.iter().any(|r| &r.name == remote) => validator.error(
+                    format!("{app_path}.remote"),
+                    format!("{remote} is not one of the configured remotes"),
+                ),
+                None if self.remotes.is_empty() => validator.error(
+                    format!("{app_path}.remote"),
+                    "applications can only be installed from a configured remote",
+                ),
+                _ => {}
+            }
+        }
+        for (i, flatpak_override) in self.overrides.iter().enumerate() {
+            validator.validate(&format!("{path}.overrides[{i}]"), flatpak_override);
+        }
+    }
+}
+
+impl Validate for FlatpakOverride {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        if let Some(app) = self.app.as_ref().filter(|a| !is_valid_app_id(a)) {
+            validator.error(
+                format!("{path}.app"),
+                format!("`{app}` is not a valid application ID"),
+            );
+        }
+        for (i, filesystem) in self.filesystems.iter().enumerate() {
+            if filesystem.trim_start_matches('!').is_empty() {
+                validator.error(
+                    format!("{path}.filesystems[{i}]"),
+                    "the filesystem must not be empty",
+                );
+            }
+        }
+        for (field, values, known) in [
+            ("sockets", &self.sockets, SOCKETS),
+            ("devices", &self.devices, DEVICES),
+        ] {
+            for (i, value) in values.iter().enumerate() {
+                if !known.contains(&value.trim_start_matches('!')) {
+                    validator.error(
+                        format!("{path}.{field}[{i}]"),
+                        format!("`{value}` must be one of {}", known.join(", ")),
+                    );
+                }
+            }
+        }
+        for name in self.environment.keys() {
+            let is_valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+            if !is_valid_name {
+                validator.error(
+                    format!("{path}.environment.{name}"),
+                    format!("`{name}` is not a valid environment variable name"),
+                );
+            }
+        }
+    }
+}
+
+/// Returns if the ID consists of at least three elements separated by dots like `org.gnome.Maps`.
+/// Elements only contain ASCII letters, digits, underscores and hyphens and don't start with a digit
+fn is_valid_app_id(id: &str) -> bool {
+    let elements = id.split('.').collect::<Vec<_>>();
+
+    id.len() <= 255
+        && elements.len() >= 3
+        && elements.iter().all(|e| {
+            !e.is_empty()
+                && !e.starts_with(|c: char| c.is_ascii_digit())
+                && e.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        })
+}
+
+/// Returns if the value is an application ID or a ref in the `app/<id>/<arch>/<branch>`
+/// format. The architecture and branch can be left empty to use the defaults
+fn is_valid_app_ref(app_ref: &str) -> bool {
+    match app_ref.split('/').collect::<Vec<_>>()[..] {
+        [id] => is_valid_app_id(id),
+        ["app", id, _, _] => is_valid_app_id(id),
+        _ => false,
+    }
+}
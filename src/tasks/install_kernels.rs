@@ -1,18 +1,561 @@
+use std::path::{Path, PathBuf};
+
 use embed_nu::rusty_value::*;
-use serde::Deserialize;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    partitioning::{ResumeDevice, RootDevice, StorageLayer},
+    script,
+    validation::{Validate, Validator},
+};
+
+/// The hooks of the default mkinitcpio.conf
+const DEFAULT_MKINITCPIO_HOOKS: &[&str] = &[
+    "base",
+    "udev",
+    "autodetect",
+    "microcode",
+    "modconf",
+    "kms",
+    "keyboard",
+    "keymap",
+    "consolefont",
+    "block",
+    "filesystems",
+    "fsck",
+];
+
+/// The mkinitcpio hook the required hooks are inserted before
+const MKINITCPIO_FILESYSTEMS_HOOK: &str = "filesystems";
 
-use crate::script;
+/// The mkinitcpio hook replacing the busybox based init with systemd
+const MKINITCPIO_SYSTEMD_HOOK: &str = "systemd";
+
+/// The mkinitcpio hook unlocking encrypted devices with the busybox based init
+const MKINITCPIO_ENCRYPT_HOOK: &str = "encrypt";
+
+/// Kernel parameters that are set by tourmaline and can't be overridden
+const DERIVED_PARAMS: &[&str] = &[
+    "root=",
+    "cryptdevice=",
+    "rd.luks.uuid=",
+    "rd.luks.name=",
+    "resume=",
+];
 
 script!(InstallKernelsScript {
     file = "install-kernels"
-    args = KernelConfig
+    args = InstallKernelsArgs
+    after = ["install-base"]
 });
 
-#[derive(Clone, Debug, RustyValue, Deserialize)]
+/// The arguments passed to the install-kernels script
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue)]
+pub struct InstallKernelsArgs {
+    /// The default kernel followed by the additional kernels
+    pub kernels: Vec<InstalledKernel>,
+    /// The initramfs configuration with the hooks required to boot from the root device
+    pub initramfs: InitramfsArgs,
+    /// The complete kernel command line including the parameters derived from the partitions
+    pub kernel_cmdline: Vec<String>,
+}
+
+/// The resolved initramfs configuration
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue)]
+pub struct InitramfsArgs {
+    pub generator: InitramfsGenerator,
+    /// The mkinitcpio hooks or dracut modules in order
+    pub hooks: Vec<String>,
+    pub modules: Vec<String>,
+    /// The storage layers below the root filesystem. Booster enables its LVM and RAID support from these
+    pub layers: Vec<StorageLayer>,
+}
+
+#[derive(Clone, Debug, RustyValue, Deserialize, Serialize, JsonSchema)]
 pub struct KernelConfig {
     pub default: Kernel,
     pub additional: Vec<Kernel>,
 }
 
-#[derive(Clone, Debug, RustyValue, Deserialize)]
-pub struct Kernel(pub String);
+/// A kernel package given either by name or with options
+#[derive(Clone, Debug, RustyValue, Deserialize, Serialize)]
+#[serde(from = "KernelEntry")]
+pub struct Kernel {
+    /// The name of the kernel package, e.g. `linux-lts`
+    pub name: String,
+    /// Installs the matching `-headers` package
+    pub headers: bool,
+    /// Kernel parameters only used for this kernel
+    pub cmdline: Vec<String>,
+    /// Generates an initramfs without autodetection and adds a boot entry for it
+    pub fallback_initramfs: bool,
+    /// Boots this kernel by default instead of the default kernel
+    pub boot_default: bool,
+}
+
+/// The kernel package name or the options described in [Kernel]
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum KernelEntry {
+    Name(String),
+    Options {
+        name: String,
+        #[serde(default)]
+        headers: bool,
+        #[serde(default)]
+        cmdline: Vec<String>,
+        #[serde(default)]
+        fallback_initramfs: bool,
+        #[serde(default)]
+        boot_default: bool,
+    },
+}
+
+/// A kernel with the files and kernel parameters resolved for the installation
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue)]
+pub struct InstalledKernel {
+    pub name: String,
+    /// The kernel package followed by the headers if requested
+    pub packages: Vec<String>,
+    /// The kernel image. Paths are relative to the root of the installed system
+    pub linux: PathBuf,
+    pub initramfs: PathBuf,
+    pub fallback_initramfs: Option<PathBuf>,
+    /// The complete kernel command line of this kernel
+    pub cmdline: Vec<String>,
+    /// Whether the kernel is booted by default
+    pub boot_default: bool,
+}
+
+#[derive(Clone, Debug, Default, RustyValue, Deserialize, Serialize, JsonSchema)]
+pub struct InitramfsConfig {
+    #[serde(default)]
+    pub generator: InitramfsGenerator,
+    /// The mkinitcpio hooks or dracut modules. Defaults to the hooks of the distribution.
+    /// Hooks required for encryption, LVM, RAID and hibernation are added automatically
+    #[serde(default)]
+    pub hooks: Vec<String>,
+    /// Kernel modules included in the initramfs
+    #[serde(default)]
+    pub modules: Vec<String>,
+}
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, RustyValue, Deserialize, Serialize, JsonSchema,
+)]
+pub enum InitramfsGenerator {
+    #[default]
+    Mkinitcpio,
+    Dracut,
+    Booster,
+}
+
+impl Kernel {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            headers: false,
+            cmdline: Vec::new(),
+            fallback_initramfs: false,
+            boot_default: false,
+        }
+    }
+}
+
+impl From<KernelEntry> for Kernel {
+    fn from(entry: KernelEntry) -> Self {
+        match entry {
+            KernelEntry::Name(name) => Self::new(name),
+            KernelEntry::Options {
+                name,
+                headers,
+                cmdline,
+                fallback_initramfs,
+                boot_default,
+            } => Self {
+                name,
+                headers,
+                cmdline,
+                fallback_initramfs,
+                boot_default,
+            },
+        }
+    }
+}
+
+impl JsonSchema for Kernel {
+    fn schema_name() -> String {
+        "Kernel".into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        KernelEntry::json_schema(gen)
+    }
+}
+
+impl KernelConfig {
+    /// Returns the default kernel followed by the additional kernels
+    pub fn kernels(&self) -> impl Iterator<Item = &Kernel> {
+        std::iter::once(&self.default).chain(&self.additional)
+    }
+
+    /// Returns the name of the kernel booted by default. The default entry of the
+    /// bootloader takes precedence over the kernel options
+    pub fn boot_default<'a>(&'a self, default_entry: Option<&'a str>) -> &'a str {
+        default_entry
+            .or_else(|| {
+                self.kernels()
+                    .find(|k| k.boot_default)
+                    .map(|k| k.name.as_str())
+            })
+            .unwrap_or(&self.default.name)
+    }
+
+    /// Resolves the packages, images and command line of all kernels
+    pub fn resolve(
+        &self,
+        generator: InitramfsGenerator,
+        kernel_cmdline: &[String],
+        default_entry: Option<&str>,
+    ) -> Vec<InstalledKernel> {
+        let boot_default = self.boot_default(default_entry);
+
+        self.kernels()
+            .map(|kernel| InstalledKernel {
+                name: kernel.name.clone(),
+                packages: std::iter::once(kernel.name.clone())
+                    .chain(kernel.headers.then(|| format!("{}-headers", kernel.name)))
+                    .collect(),
+                linux: Path::new("/boot").join(format!("vmlinuz-{}", kernel.name)),
+                initramfs: generator.image(&kernel.name, false),
+                fallback_initramfs: kernel
+                    .fallback_initramfs
+                    .then(|| generator.image(&kernel.name, true)),
+                cmdline: kernel_cmdline
+                    .iter()
+                    .chain(&kernel.cmdline)
+                    .cloned()
+                    .collect(),
+                boot_default: kernel.name == boot_default,
+            })
+            .collect()
+    }
+}
+
+impl InitramfsGenerator {
+    /// Returns the path of the initramfs image the generator creates for the kernel
+    pub fn image(&self, kernel: &str, fallback: bool) -> PathBuf {
+        let name = match (self, fallback) {
+            (InitramfsGenerator::Booster, _) => format!("booster-{kernel}.img"),
+            (_, false) => format!("initramfs-{kernel}.img"),
+            (_, true) => format!("initramfs-{kernel}-fallback.img"),
+        };
+
+        Path::new("/boot").join(name)
+    }
+}
+
+impl InitramfsConfig {
+    /// Returns the kernel parameters required to boot from the root device
+    /// and to resume from the swap device with this initramfs
+    pub fn kernel_params(
+        &self,
+        root: Option<&RootDevice>,
+        resume: Option<&ResumeDevice>,
+    ) -> Vec<String> {
+        let mut params = Vec::new();
+        let root = match root {
+            Some(root) => root,
+            None => return params,
+        };
+
+        if let Some(encrypted) = &root.encryption {
+            params.push(match (self.generator, &encrypted.uuid) {
+                // sd-encrypt keeps the name so the root device stays the same
+                (InitramfsGenerator::Mkinitcpio, Some(uuid)) if self.uses_systemd() => {
+                    format!("rd.luks.name={uuid}={}", encrypted.name)
+                }
+                (InitramfsGenerator::Mkinitcpio, Some(uuid)) => {
+                    format!("cryptdevice=UUID={uuid}:{}", encrypted.name)
+                }
+                (InitramfsGenerator::Mkinitcpio, None) => {
+                    format!(
+                        "cryptdevice={}:{}",
+                        encrypted.device.display(),
+                        encrypted.name
+                    )
+                }
+                // dracut and booster find the device by the UUID and name it luks-<uuid>
+                (_, Some(uuid)) => format!("rd.luks.uuid={uuid}"),
+                (_, None) => format!("rd.luks.name={}", encrypted.name),
+            });
+        }
+        params.push(match &root.uuid {
+            Some(uuid) => format!("root=UUID={uuid}"),
+            None => format!("root={}", root.device.display()),
+        });
+        if let Some(subvolume) = &root.subvolume {
+            params.push(format!("rootflags=subvol={subvolume}"));
+        }
+        if let Some(resume) = resume {
+            params.push(match &resume.uuid {
+                Some(uuid) => format!("resume=UUID={uuid}"),
+                None => format!("resume={}", resume.device.display()),
+            });
+        }
+
+        params
+    }
+
+    /// Returns if the mkinitcpio hooks use systemd instead of the busybox based init
+    fn uses_systemd(&self) -> bool {
+        self.generator == InitramfsGenerator::Mkinitcpio
+            && self.hooks.iter().any(|h| h == MKINITCPIO_SYSTEMD_HOOK)
+    }
+
+    /// Adds the hooks required to boot from the root device and to resume from swap
+    pub fn resolve(&self, root: Option<&RootDevice>, resume: bool) -> InitramfsArgs {
+        let layers = root.map(|r| r.layers.clone()).unwrap_or_default();
+        let systemd = self.uses_systemd();
+        // the layers are assembled in order from the disk up
+        let required = layers
+            .iter()
+            .map(|layer| match (self.generator, layer) {
+                (InitramfsGenerator::Mkinitcpio, StorageLayer::Raid) => "mdadm_udev",
+                (InitramfsGenerator::Mkinitcpio, StorageLayer::Encryption) if systemd => {
+                    "sd-encrypt"
+                }
+                (InitramfsGenerator::Mkinitcpio, StorageLayer::Encryption) => {
+                    MKINITCPIO_ENCRYPT_HOOK
+                }
+                (InitramfsGenerator::Mkinitcpio, StorageLayer::Lvm) => "lvm2",
+                (_, StorageLayer::Raid) => "mdraid",
+                (_, StorageLayer::Encryption) => "crypt",
+                (_, StorageLayer::Lvm) => "lvm",
+            })
+            // systemd resumes from hibernation without a hook
+            .chain((resume && !systemd).then_some("resume"))
+            .filter(|hook| !self.hooks.iter().any(|h| h == hook));
+        let hooks = match self.generator {
+            InitramfsGenerator::Mkinitcpio => {
+                let mut hooks = if self.hooks.is_empty() {
+                    DEFAULT_MKINITCPIO_HOOKS
+                        .iter()
+                        .map(|h| h.to_string())
+                        .collect()
+                } else {
+                    self.hooks.clone()
+                };
+                let position = hooks
+                    .iter()
+                    .position(|h| h == MKINITCPIO_FILESYSTEMS_HOOK)
+                    .unwrap_or(hooks.len());
+                hooks.splice(position..position, required.map(String::from));
+
+                hooks
+            }
+            InitramfsGenerator::Dracut => self
+                .hooks
+                .iter()
+                .cloned()
+                .chain(required.map(String::from))
+                .collect(),
+            InitramfsGenerator::Booster => Vec::new(),
+        };
+
+        InitramfsArgs {
+            generator: self.generator,
+            hooks,
+            modules: self.modules.clone(),
+            layers,
+        }
+    }
+}
+
+impl KernelConfig {
+    /// Checks the kernel options that depend on the partitions and the initramfs generator
+    pub(crate) fn validate_setup(
+        &self,
+        path: &str,
+        initramfs: &InitramfsConfig,
+        derived_cmdline: bool,
+        validator: &mut Validator,
+    ) {
+        let kernels = std::iter::once((format!("{path}.default"), &self.default)).chain(
+            self.additional
+                .iter()
+                .enumerate()
+                .map(|(i, k)| (format!("{path}.additional[{i}]"), k)),
+        );
+
+        for (path, kernel) in kernels {
+            validate_kernel_cmdline(
+                &format!("{path}.cmdline"),
+                &kernel.cmdline,
+                derived_cmdline,
+                validator,
+            );
+            if kernel.fallback_initramfs && initramfs.generator == InitramfsGenerator::Booster {
+                validator.error(
+                    format!("{path}.fallback_initramfs"),
+                    "booster doesn't generate fallback images",
+                );
+            }
+        }
+    }
+}
+
+impl Validate for KernelConfig {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        if self.default.name.is_empty() {
+            validator.error(
+                format!("{path}.default"),
+                "the kernel name must not be empty",
+            );
+        }
+        for (i, kernel) in self.additional.iter().enumerate() {
+            if kernel.name.is_empty() {
+                validator.error(
+                    format!("{path}.additional[{i}]"),
+                    "the kernel name must not be empty",
+                );
+            } else if kernel.name == self.default.name {
+                validator.error(
+                    format!("{path}.additional[{i}]"),
+                    format!("{} is already the default kernel", kernel.name),
+                );
+            } else if self.additional[..i].iter().any(|k| k.name == kernel.name) {
+                validator.error(
+                    format!("{path}.additional[{i}]"),
+                    format!("{} is installed more than once", kernel.name),
+                );
+            }
+        }
+        if self.kernels().filter(|k| k.boot_default).count() > 1 {
+            validator.error(path, "only one kernel can be booted by default");
+        }
+    }
+}
+
+impl Validate for InitramfsConfig {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        if self.generator == InitramfsGenerator::Booster && !self.hooks.is_empty() {
+            validator.error(
+                format!("{path}.hooks"),
+                "booster doesn't support hooks. Use modules instead",
+            );
+        }
+        if self.hooks.iter().any(String::is_empty) {
+            validator.error(format!("{path}.hooks"), "hooks must not be empty");
+        }
+        if self.modules.iter().any(String::is_empty) {
+            validator.error(format!("{path}.modules"), "modules must not be empty");
+        }
+        if self.uses_systemd() && self.hooks.iter().any(|h| h == MKINITCPIO_ENCRYPT_HOOK) {
+            validator.error(
+                format!("{path}.hooks"),
+                "the encrypt hook doesn't work together with the systemd hook. Use sd-encrypt instead",
+            );
+        }
+    }
+}
+
+/// Checks the kernel parameters given in the config. Parameters that are derived
+/// from the partitions can only be set if the partitions are created automatically
+pub(crate) fn validate_kernel_cmdline(
+    path: &str,
+    params: &[String],
+    derived: bool,
+    validator: &mut Validator,
+) {
+    for (i, param) in params.iter().enumerate() {
+        if param.is_empty() || param.contains(char::is_whitespace) {
+            validator.error(
+                format!("{path}[{i}]"),
+                "kernel parameters must not be empty or contain whitespace",
+            );
+        } else if let Some(param) = DERIVED_PARAMS
+            .iter()
+            .find(|p| derived && param.starts_with(*p))
+        {
+            validator.error(
+                format!("{path}[{i}]"),
+                format!("{param} is derived from the partitions and can't be set"),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encryption::EncryptedDevice;
+
+    use super::*;
+
+    fn encrypted_root() -> RootDevice {
+        RootDevice {
+            device: PathBuf::from("/dev/mapper/cryptroot"),
+            uuid: Some("root-uuid".into()),
+            subvolume: None,
+            encryption: Some(EncryptedDevice {
+                device: PathBuf::from("/dev/sda2"),
+                name: "cryptroot".into(),
+                mapper: PathBuf::from("/dev/mapper/cryptroot"),
+                uuid: Some("luks-uuid".into()),
+                tpm2: false,
+            }),
+            layers: vec![StorageLayer::Encryption, StorageLayer::Lvm],
+        }
+    }
+
+    fn mkinitcpio(hooks: &[&str]) -> InitramfsConfig {
+        InitramfsConfig {
+            generator: InitramfsGenerator::Mkinitcpio,
+            hooks: hooks.iter().map(|h| h.to_string()).collect(),
+            modules: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn adds_busybox_hooks_before_filesystems() {
+        let initramfs = mkinitcpio(&[]).resolve(Some(&encrypted_root()), true);
+        let position = |hook| initramfs.hooks.iter().position(|h| h == hook).unwrap();
+
+        assert!(position("encrypt") < position("lvm2"));
+        assert!(position("lvm2") < position("resume"));
+        assert!(position("resume") < position("filesystems"));
+
+        let params = mkinitcpio(&[]).kernel_params(Some(&encrypted_root()), None);
+        assert_eq!(params[0], "cryptdevice=UUID=luks-uuid:cryptroot");
+    }
+
+    #[test]
+    fn uses_sd_encrypt_with_systemd_hooks() {
+        let config = mkinitcpio(&["base", "systemd", "autodetect", "block", "filesystems"]);
+        let initramfs = config.resolve(Some(&encrypted_root()), true);
+
+        assert_eq!(
+            initramfs.hooks,
+            [
+                "base",
+                "systemd",
+                "autodetect",
+                "block",
+                "sd-encrypt",
+                "lvm2",
+                "filesystems"
+            ]
+        );
+        let params = config.kernel_params(Some(&encrypted_root()), None);
+        assert_eq!(params[0], "rd.luks.name=luks-uuid=cryptroot");
+    }
+
+    #[test]
+    fn rejects_the_encrypt_hook_with_systemd() {
+        let mut validator = Validator::default();
+        validator.validate("$", &mkinitcpio(&["base", "systemd", "encrypt"]));
+
+        assert_eq!(validator.into_errors().len(), 1);
+    }
+}
@@ -1,8 +1,38 @@
-use crate::script;
+use std::path::PathBuf;
+
+use embed_nu::rusty_value::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{partitioning::RootDevice, script};
+
+/// The subvolume timeshift expects the root filesystem in for btrfs snapshots
+const TIMESHIFT_ROOT_SUBVOLUME: &str = "@";
 
 script!(InstallTimeshiftScript {
     file = "install-timeshift"
-    args = TimeshiftConfig
+    args = InstallTimeshiftArgs
+    after = ["install-base"]
 });
 
-pub type TimeshiftConfig = ();
+/// The arguments passed to the install-timeshift script
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue)]
+pub struct InstallTimeshiftArgs {
+    /// Whether timeshift can use btrfs snapshots instead of rsync.
+    /// This requires the root filesystem to be mounted from the `@` subvolume
+    pub btrfs_mode: bool,
+    /// The btrfs device holding the root subvolume in btrfs mode
+    pub device: Option<PathBuf>,
+}
+
+impl InstallTimeshiftArgs {
+    pub fn new(root: Option<&RootDevice>) -> Self {
+        let device = root
+            .filter(|r| r.subvolume.as_deref() == Some(TIMESHIFT_ROOT_SUBVOLUME))
+            .map(|r| r.device.clone());
+
+        Self {
+            btrfs_mode: device.is_some(),
+            device,
+        }
+    }
+}
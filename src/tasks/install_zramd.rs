@@ -3,6 +3,7 @@ use crate::script;
 script!(InstallZRamDScript {
     file = "install-zramd"
     args = ZRamDConfig
+    after = ["install-base"]
 });
 
 pub type ZRamDConfig = ();
@@ -2,6 +2,7 @@ mod configure_locale;
 mod configure_network;
 mod configure_unakite;
 mod create_partitions;
+mod custom;
 mod install_base;
 mod install_bootloader;
 mod install_desktop;
@@ -19,6 +20,7 @@ pub use configure_locale::*;
 pub use configure_network::*;
 pub use configure_unakite::*;
 pub use create_partitions::*;
+pub use custom::*;
 pub use install_base::*;
 pub use install_bootloader::*;
 pub use install_desktop::*;
@@ -30,15 +32,77 @@ pub use install_zramd::*;
 pub use setup_root_user::*;
 pub use setup_users::*;
 
-use crate::scripting::script::Script;
+use crate::{
+    error::AppResult,
+    scripting::{loader::HookType, script::Script},
+    TaskExecutor,
+};
 
+#[derive(Clone, Debug)]
 pub struct TaskFiles {
     script: String,
     pre_hook: String,
     post_hook: String,
+    dependencies: Vec<String>,
+    dependents: Vec<String>,
+    secrets: bool,
 }
 
 impl TaskFiles {
+    pub(crate) fn of<S: Script>() -> Self {
+        Self {
+            script: S::get_name().into(),
+            pre_hook: S::get_pre_hook().into(),
+            post_hook: S::get_post_hook().into(),
+            dependencies: S::get_dependencies()
+                .iter()
+                .map(|d| d.to_string())
+                .collect(),
+            dependents: Vec::new(),
+            secrets: false,
+        }
+    }
+
+    /// Returns the name of the task without the file extension
+    pub fn name(&self) -> &str {
+        self.script.trim_end_matches(".nu")
+    }
+
+    pub fn script(&self) -> &str {
+        &self.script
+    }
+
+    pub fn pre_hook(&self) -> &str {
+        &self.pre_hook
+    }
+
+    pub fn post_hook(&self) -> &str {
+        &self.post_hook
+    }
+
+    /// Returns the names of the tasks that need to be executed before this one
+    pub fn dependencies(&self) -> &[String] {
+        &self.dependencies
+    }
+
+    /// Returns the names of the tasks that need to be executed after this one
+    pub fn dependents(&self) -> &[String] {
+        &self.dependents
+    }
+
+    /// Returns if the task gets access to the passwords in the config
+    pub fn secrets(&self) -> bool {
+        self.secrets
+    }
+
+    /// Returns the file name of the hook with the given type
+    pub fn hook(&self, hook_type: HookType) -> &str {
+        match hook_type {
+            HookType::Pre => &self.pre_hook,
+            HookType::Post => &self.post_hook,
+        }
+    }
+
     pub fn script_path(&self, base: &Path) -> PathBuf {
         base.join("scripts").join(&self.script)
     }
@@ -52,35 +116,73 @@ impl TaskFiles {
     }
 }
 
-macro_rules! __all_tasks {
-    ($($task:ident),+) => {
-        {
-            vec![$(
-                TaskFiles {
-                    script: $task::get_name().into(),
-                    pre_hook: $task::get_pre_hook().into(),
-                    post_hook: $task::get_post_hook().into(),
-                },
-            )+]
+macro_rules! tasks {
+    ($($variant:ident => $script:ident),+) => {
+        /// A task together with the arguments
+        /// its script and hooks get called with
+        // tasks are only created once per installation so their size doesn't matter
+        #[allow(clippy::large_enum_variant)]
+        #[derive(Clone, Debug)]
+        pub enum Task {
+            $($variant(<$script as Script>::Args),)+
+            Custom(CustomTask),
+        }
+
+        impl Task {
+            /// Returns the script and hook files of the task
+            pub fn files(&self) -> TaskFiles {
+                match self {
+                    $(Self::$variant(_) => TaskFiles::of::<$script>(),)+
+                    Self::Custom(task) => task.manifest.files(),
+                }
+            }
+
+            /// Returns the arguments the task gets called with
+            pub fn args(&self) -> serde_json::Result<serde_json::Value> {
+                match self {
+                    $(Self::$variant(args) => serde_json::to_value(args),)+
+                    Self::Custom(task) => serde_json::to_value(&task.args),
+                }
+            }
+
+            pub(crate) async fn execute(self, executor: &TaskExecutor) -> AppResult<()> {
+                match self {
+                    $(Self::$variant(args) => executor.execute_task::<$script>(args).await,)+
+                    Self::Custom(task) => executor.execute_custom_task(task).await,
+                }
+            }
+        }
+
+        pub fn all_tasks() -> Vec<TaskFiles> {
+            vec![$(TaskFiles::of::<$script>(),)+]
         }
     };
 }
 
-pub fn all_tasks() -> Vec<TaskFiles> {
-    __all_tasks!(
-        ConfigureLocaleScript,
-        ConfigureNetworkScript,
-        ConfigureUnakiteScript,
-        CreatePartitionsScript,
-        InstallBaseScript,
-        InstallBootloaderScript,
-        InstallDesktopScript,
-        InstallExtraPackagesScript,
-        InstallFlatpakScript,
-        InstallKernelsScript,
-        InstallTimeshiftScript,
-        InstallZRamDScript,
-        SetupRootUserScript,
-        SetupUsersScript
-    )
+tasks!(
+    ConfigureLocale => ConfigureLocaleScript,
+    ConfigureNetwork => ConfigureNetworkScript,
+    ConfigureUnakite => ConfigureUnakiteScript,
+    CreatePartitions => CreatePartitionsScript,
+    InstallBase => InstallBaseScript,
+    InstallBootloader => InstallBootloaderScript,
+    InstallDesktop => InstallDesktopScript,
+    InstallExtraPackages => InstallExtraPackagesScript,
+    InstallFlatpak => InstallFlatpakScript,
+    InstallKernels => InstallKernelsScript,
+    InstallTimeshift => InstallTimeshiftScript,
+    InstallZRamD => InstallZRamDScript,
+    SetupRootUser => SetupRootUserScript,
+    SetupUsers => SetupUsersScript
+);
+
+impl Task {
+    /// Checks the system installed at the given root for what the task relies on
+    /// and returns warnings about anything that is missing
+    pub(crate) fn check_target(&self, root: &Path) -> Vec<String> {
+        match self {
+            Task::SetupUsers(users) => users.check_shells(root),
+            _ => Vec::new(),
+        }
+    }
 }
@@ -1,14 +1,26 @@
 use embed_nu::rusty_value::*;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
-use crate::script;
+use crate::{
+    script,
+    secrets::Password,
+    validation::{Validate, Validator},
+};
 
 script!(SetupRootUserScript {
     file = "setup-root-user"
     args = RootUserConfig
+    after = ["install-base"]
 });
 
-#[derive(Clone, Debug, Deserialize, RustyValue)]
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
 pub struct RootUserConfig {
-    pub password: String,
+    pub password: Password,
+}
+
+impl Validate for RootUserConfig {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        validator.validate(&format!("{path}.password"), &self.password);
+    }
 }
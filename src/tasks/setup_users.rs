@@ -1,22 +1,276 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
 use embed_nu::rusty_value::*;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    script,
+    secrets::Password,
+    validation::{is_valid_username, Validate, Validator},
+};
 
-use crate::script;
+/// The range of IDs useradd assigns to regular users and their groups
+const MIN_ID: u32 = 1000;
+const MAX_ID: u32 = 60000;
+
+/// The key types accepted in `authorized_keys`
+const SSH_KEY_TYPES: &[&str] = &[
+    "ssh-ed25519",
+    "ssh-rsa",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "sk-ssh-ed25519@openssh.com",
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+];
 
 script!(SetupUsersScript {
     file = "setup-users"
     args = UsersConfig
+    after = ["install-base"]
 });
 
-#[derive(Clone, Debug, Deserialize, RustyValue)]
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
 pub struct UsersConfig {
     pub users: Vec<User>,
 }
 
-#[derive(Clone, Debug, Deserialize, RustyValue)]
+#[derive(Clone, Debug, Deserialize, Serialize, RustyValue, JsonSchema)]
 pub struct User {
     pub name: String,
-    pub password: String,
+    pub password: Password,
     pub sudoer: bool,
     pub shell: String,
+    /// The full name stored in the GECOS field
+    pub full_name: Option<String>,
+    /// A fixed user ID. Assigned by useradd if not set
+    pub uid: Option<u32>,
+    /// A fixed ID for the primary group named after the user
+    pub gid: Option<u32>,
+    /// Supplementary groups. Groups that don't exist yet are created
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// The home directory. Defaults to `/home/<name>`
+    pub home: Option<PathBuf>,
+    /// The directory the home directory is populated from. Defaults to `/etc/skel`
+    pub skel: Option<PathBuf>,
+    /// Public keys added to `~/.ssh/authorized_keys`
+    #[serde(default)]
+    pub ssh_keys: Vec<String>,
+    /// Locks the password so the user can only log in with SSH keys or autologin
+    #[serde(default)]
+    pub locked: bool,
+    /// Logs the user in to the desktop automatically
+    #[serde(default)]
+    pub autologin: bool,
+}
+
+impl Validate for UsersConfig {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        let mut names = HashSet::new();
+        let mut uids = HashSet::new();
+        let mut gids = HashSet::new();
+
+        for (i, user) in self.users.iter().enumerate() {
+            let user_path = format!("{path}.users[{i}]");
+            validator.validate(&user_path, user);
+
+            if !names.insert(&user.name) {
+                validator.error(
+                    format!("{user_path}.name"),
+                    format!("the user {} is defined more than once", user.name),
+                );
+            }
+            if let Some(uid) = user.uid.filter(|uid| !uids.insert(*uid)) {
+                validator.error(
+                    format!("{user_path}.uid"),
+                    format!("the uid {uid} is used by more than one user"),
+                );
+            }
+            if let Some(gid) = user.gid.filter(|gid| !gids.insert(*gid)) {
+                validator.error(
+                    format!("{user_path}.gid"),
+                    format!("the gid {gid} is used by more than one user"),
+                );
+            }
+        }
+        if self.users.iter().filter(|u| u.autologin).count() > 1 {
+            validator.error(
+                format!("{path}.users"),
+                "only one user can be logged in automatically",
+            );
+        }
+    }
+}
+
+impl UsersConfig {
+    /// Returns a warning for every shell that isn't listed in the `/etc/shells`
+    /// of the system installed at the given root. Packages installed by later tasks
+    /// can still provide the shell, so these don't stop the installation
+    pub(crate) fn check_shells(&self, root: &Path) -> Vec<String> {
+        let shells = match fs::read_to_string(root.join("etc/shells")) {
+            Ok(shells) => shells,
+            Err(e) => {
+                return vec![format!(
+                    "Could not read /etc/shells of the installed system: {e}"
+                )]
+            }
+        };
+        let shells = shells
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .collect::<HashSet<_>>();
+
+        self.users
+            .iter()
+            .filter(|u| !shells.contains(u.shell.as_str()))
+            .map(|u| {
+                format!(
+                    "The shell {} of the user {} is not listed in /etc/shells of the installed system",
+                    u.shell, u.name
+                )
+            })
+            .collect()
+    }
+}
+
+impl Validate for User {
+    fn validate(&self, path: &str, validator: &mut Validator) {
+        if self.name.is_empty() {
+            validator.error(format!("{path}.name"), "the name must not be empty");
+        } else if self.name == "root" {
+            validator.error(
+                format!("{path}.name"),
+                "the root user is configured with root_user",
+            );
+        } else if !is_valid_username(&self.name) {
+            validator.error(
+                format!("{path}.name"),
+                "the name must start with a lowercase letter or underscore followed by at most 31 lowercase letters, digits, underscores or dashes",
+            );
+        }
+        validator.validate(&format!("{path}.password"), &self.password);
+
+        if !self.shell.starts_with('/') {
+            validator.error(
+                format!("{path}.shell"),
+                "the shell must be an absolute path",
+            );
+        }
+        if let Some(full_name) = &self.full_name {
+            // the GECOS field is separated by colons and its subfields by commas
+            if full_name.contains([':', ',', '\n']) {
+                validator.error(
+                    format!("{path}.full_name"),
+                    "the full name must not contain colons, commas or line breaks",
+                );
+            }
+        }
+        for (field, id) in [("uid", self.uid), ("gid", self.gid)] {
+            if let Some(id) = id.filter(|id| !(MIN_ID..=MAX_ID).contains(id)) {
+                validator.error(
+                    format!("{path}.{field}"),
+                    format!("{id} is outside of the range {MIN_ID}-{MAX_ID} for regular users"),
+                );
+            }
+        }
+        let mut groups = HashSet::new();
+
+        for (i, group) in self.groups.iter().enumerate() {
+            if !is_valid_username(group) {
+                validator.error(
+                    format!("{path}.groups[{i}]"),
+                    format!("{group} is not a valid group name"),
+                );
+            } else if !groups.insert(group) {
+                validator.error(
+                    format!("{path}.groups[{i}]"),
+                    format!("the group {group} is listed more than once"),
+                );
+            }
+        }
+        for (field, dir) in [("home", &self.home), ("skel", &self.skel)] {
+            if dir.as_ref().is_some_and(|d| !d.is_absolute()) {
+                validator.error(
+                    format!("{path}.{field}"),
+                    "the directory must be an absolute path",
+                );
+            }
+        }
+        for (i, key) in self.ssh_keys.iter().enumerate() {
+            if !is_valid_ssh_key(key) {
+                validator.error(
+                    format!("{path}.ssh_keys[{i}]"),
+                    "the key must be a public key in the authorized_keys format",
+                );
+            }
+        }
+    }
+}
+
+/// Checks that the key consists of a known type, the base64 encoded key and an optional comment
+fn is_valid_ssh_key(key: &str) -> bool {
+    let mut parts = key.split_whitespace();
+
+    match (parts.next(), parts.next()) {
+        (Some(key_type), Some(data)) => {
+            SSH_KEY_TYPES.contains(&key_type)
+                && data
+                    .trim_end_matches('=')
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '/')
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn users(shells: &[&str]) -> UsersConfig {
+        let users = shells
+            .iter()
+            .enumerate()
+            .map(|(i, shell)| {
+                serde_json::json!({
+                    "name": format!("user{i}"),
+                    "password": "secret",
+                    "sudoer": false,
+                    "shell": shell,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        serde_json::from_value(serde_json::json!({ "users": users })).unwrap()
+    }
+
+    #[test]
+    fn warns_about_shells_missing_in_the_target() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("etc")).unwrap();
+        fs::write(
+            root.path().join("etc/shells"),
+            "# Pathnames of valid login shells.\n/bin/sh\n/bin/bash\n/usr/bin/zsh\n",
+        )
+        .unwrap();
+
+        let warnings =
+            users(&["/bin/bash", "/usr/bin/zsh", "/usr/bin/fish"]).check_shells(root.path());
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("/usr/bin/fish of the user user2"));
+    }
+
+    #[test]
+    fn warns_if_the_target_has_no_shells_file() {
+        let root = tempfile::tempdir().unwrap();
+
+        assert_eq!(users(&["/bin/bash"]).check_shells(root.path()).len(), 1);
+    }
 }
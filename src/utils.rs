@@ -8,11 +8,19 @@ use crate::error::AppResult;
 use crate::tasks::all_tasks;
 
 const DEFAULT_CONFIG_DIR: &str = "/etc";
+const DEFAULT_STATE_DIR: &str = "/var/lib";
 
 lazy_static::lazy_static! {
     pub static ref CFG_PATH: PathBuf = env::var("TRM_CFG_PATH").map(PathBuf::from).unwrap_or_else(|_| PathBuf::from(DEFAULT_CONFIG_DIR).join("tourmaline"));
     pub static ref SCRIPT_PATH: PathBuf = CFG_PATH.join("scripts");
     pub static ref HOOK_PATH: PathBuf = CFG_PATH.join("hooks");
+    pub static ref TASK_PATH: PathBuf = CFG_PATH.join("tasks");
+    pub static ref SYSFS_PATH: PathBuf = env::var("TRM_SYSFS_PATH").map(PathBuf::from).unwrap_or_else(|_| PathBuf::from("/sys"));
+    pub static ref PROCFS_PATH: PathBuf = env::var("TRM_PROCFS_PATH").map(PathBuf::from).unwrap_or_else(|_| PathBuf::from("/proc"));
+    pub static ref UDEV_DATA_PATH: PathBuf = env::var("TRM_UDEV_DATA_PATH").map(PathBuf::from).unwrap_or_else(|_| PathBuf::from("/run/udev/data"));
+    pub static ref LOCALE_ROOT_PATH: PathBuf = env::var("TRM_LOCALE_ROOT_PATH").map(PathBuf::from).unwrap_or_else(|_| PathBuf::from("/"));
+    pub static ref TARGET_PATH: PathBuf = env::var("TRM_TARGET_PATH").map(PathBuf::from).unwrap_or_else(|_| PathBuf::from("/mnt"));
+    pub static ref JOURNAL_PATH: PathBuf = env::var("TRM_JOURNAL_PATH").map(PathBuf::from).unwrap_or_else(|_| PathBuf::from(DEFAULT_STATE_DIR).join("tourmaline").join("journal.json"));
 }
 
 pub async fn generate_script_files<P: AsRef<Path>>(output: P) -> AppResult<()> {
@@ -0,0 +1,142 @@
+use std::{fmt, net::IpAddr};
+
+use serde::Serialize;
+
+/// A problem found while validating the config
+#[derive(Clone, Debug, Serialize)]
+pub struct ValidationError {
+    /// The JSON path of the offending value, e.g. `$.users.users[0].name`
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Collects all problems found in a config
+#[derive(Default)]
+pub struct Validator {
+    errors: Vec<ValidationError>,
+}
+
+impl Validator {
+    pub fn error<P: ToString, M: ToString>(&mut self, path: P, message: M) {
+        self.errors.push(ValidationError {
+            path: path.to_string(),
+            message: message.to_string(),
+        })
+    }
+
+    /// Validates the given value at the given path
+    pub fn validate<V: Validate>(&mut self, path: &str, value: &V) -> &mut Self {
+        value.validate(path, self);
+
+        self
+    }
+
+    pub fn into_errors(self) -> Vec<ValidationError> {
+        self.errors
+    }
+}
+
+/// A config section that can be checked for semantic errors
+pub trait Validate {
+    /// Validates the value and reports all errors relative to the given path
+    fn validate(&self, path: &str, validator: &mut Validator);
+}
+
+/// Returns if the given name is a valid hostname as defined in RFC 1123
+pub fn is_valid_hostname(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Returns if the given string is an IPv4 or IPv6 address
+pub fn is_valid_ip(address: &str) -> bool {
+    address.parse::<IpAddr>().is_ok()
+}
+
+/// Returns if the given string is an IP address with a prefix length, e.g. `10.0.0.1/8`
+pub fn is_valid_cidr(cidr: &str) -> bool {
+    let Some((address, prefix)) = cidr.split_once('/') else {
+        return false;
+    };
+    let max_prefix = match address.parse::<IpAddr>() {
+        Ok(IpAddr::V4(_)) => 32,
+        Ok(IpAddr::V6(_)) => 128,
+        Err(_) => return false,
+    };
+
+    // parse accepts a leading `+`
+    prefix.bytes().all(|b| b.is_ascii_digit())
+        && prefix
+            .parse::<u8>()
+            .is_ok_and(|prefix| prefix <= max_prefix)
+}
+
+/// Returns if the given name is a valid user or group name as accepted by useradd
+pub fn is_valid_username(name: &str) -> bool {
+    let name = name.strip_suffix('$').unwrap_or(name);
+
+    !name.is_empty()
+        && name.len() <= 32
+        && name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_cidrs_within_the_prefix_range() {
+        for cidr in [
+            "10.0.0.1/8",
+            "192.168.1.10/0",
+            "192.168.1.10/32",
+            "fd00::1/64",
+            "::1/128",
+        ] {
+            assert!(is_valid_cidr(cidr), "{cidr}");
+        }
+    }
+
+    #[test]
+    fn rejects_cidrs_without_a_prefix() {
+        for cidr in ["10.0.0.1", "fd00::1", "10.0.0.1/", "/24"] {
+            assert!(!is_valid_cidr(cidr), "{cidr}");
+        }
+    }
+
+    #[test]
+    fn rejects_prefixes_out_of_range() {
+        for cidr in [
+            "10.0.0.1/33",
+            "fd00::1/129",
+            "10.0.0.1/300",
+            "10.0.0.1/-1",
+            "10.0.0.1/+8",
+        ] {
+            assert!(!is_valid_cidr(cidr), "{cidr}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_addresses() {
+        for cidr in ["10.0.0.256/8", "10.0.0/8", "fd00::g/64", "localhost/8"] {
+            assert!(!is_valid_cidr(cidr), "{cidr}");
+        }
+    }
+}